@@ -1,11 +1,99 @@
+/* c-builder test framework version 1 */
 #include "test_framework.h"
 
 #include <setjmp.h>
+#include <stdarg.h>
+#include <stdio.h>
 
 jmp_buf jmpbuf;
-char *error_msg;
+char error_msg[ERROR_MSG_SIZE];
+static size_t error_len;
+static int failures;
 
-void fail(char *msg) {
-    error_msg = msg;
+/* Appends a failure to error_msg, one failure per line. */
+static void record(const char *format, va_list args) {
+    if (error_len > 0 && error_len < sizeof(error_msg) - 1) {
+        error_msg[error_len++] = '\n';
+    }
+    int len = vsnprintf(error_msg + error_len, sizeof(error_msg) - error_len, format, args);
+    if (len > 0) {
+        error_len += len;
+    }
+    if (error_len > sizeof(error_msg) - 1) {
+        error_len = sizeof(error_msg) - 1;
+    }
+    failures++;
+}
+
+void fail(const char *msg) {
+    failf("%s", msg);
+}
+
+void failf(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    record(format, args);
+    va_end(args);
     longjmp(jmpbuf, 1);
 }
+
+void expectf(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    record(format, args);
+    va_end(args);
+}
+
+void reset_failures(void) {
+    error_msg[0] = '\0';
+    error_len = 0;
+    failures = 0;
+}
+
+void print_failures(const char *prefix) {
+    const char *line = error_msg;
+    while (*line != '\0') {
+        size_t len = strcspn(line, "\n");
+        printf("%s%.*s\n", prefix, (int)len, line);
+        line += line[len] == '\n' ? len + 1 : len;
+    }
+}
+
+int run_step(test_fn *step) {
+    if (step == NULL) {
+        return 0;
+    }
+    int before = failures;
+    if (setjmp(jmpbuf) == 0) {
+        step();
+    }
+    return failures != before;
+}
+
+int run_test(struct Test *test) {
+    reset_failures();
+    int failed = run_step(test->setup) || run_step(test->test);
+    return run_step(test->teardown) || failed;
+}
+
+int run_suite_test(struct Test *suite, struct Test *test) {
+    reset_failures();
+    int failed = run_step(suite->setup) || run_test(test);
+    return run_step(suite->teardown) || failed;
+}
+
+void format_signed_value(char *buffer, long long value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%lld", value);
+}
+
+void format_unsigned_value(char *buffer, unsigned long long value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%llu", value);
+}
+
+void format_float_value(char *buffer, long double value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%Lg", value);
+}
+
+void format_pointer_value(char *buffer, const void *value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%p", value);
+}
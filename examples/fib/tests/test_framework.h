@@ -1,51 +1,212 @@
+/* c-builder test framework version 1 */
 #include <string.h>
 #include <math.h>
 
 #pragma once
+#define TEST_FRAMEWORK_VERSION 1
 #define TEST(name) void test_##name()
+#define TEST_TIMEOUT(name, ms) void test_##name()
 #define TO_STRING(x) #x
 #define STRINGIFY(x) TO_STRING(x)
+#define CONCAT_(a, b) a##b
+#define CONCAT(a, b) CONCAT_(a, b)
+#define LOCATION __FILE__ ":" STRINGIFY(__LINE__) ": "
+#define ERROR_MSG_SIZE 4096
+#define VALUE_STRING_SIZE 64
 
-#define ASSERT(cond)                                                           \
+#define SETUP() void CONCAT(setup_, TEST_FILE_ID)()
+#define TEARDOWN() void CONCAT(teardown_, TEST_FILE_ID)()
+#define SUITE_SETUP() void suite_setup()
+#define SUITE_TEARDOWN() void suite_teardown()
+
+/* Every check reports a failure with `report`, which is `failf` for the ASSERT_* macros
+   and `expectf` for the EXPECT_* macros. */
+#define CHECK(report, cond)                                                    \
     if (!(cond)) {                                                             \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #cond);                     \
+        report(LOCATION "%s", #cond);                                          \
     }
 
-#define ASSERT_EQ(a, b)                                                        \
+#define CHECK_EQ(report, a, b)                                                 \
     if ((a) != (b)) {                                                          \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != " #b);              \
+        report(LOCATION "%s", #a " != " #b);                                   \
     }
 
-#define ASSERT_STRING_EQ(a, b)                                                 \
-    if (strcmp(a, b) != 0) {                                                   \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != " #b);              \
-    }
+#define CHECK_STRING_EQ(report, a, b)                                          \
+    do {                                                                       \
+        const char *actual_ = (a), *expected_ = (b);                           \
+        if (strcmp(actual_, expected_) != 0) {                                 \
+            report(LOCATION "expected " #a " == " #b ", got \"%s\" and \"%s\"",\
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
 
-#define ASSERT_NULL(a)                                                         \
+#define CHECK_NULL(report, a)                                                  \
     if ((a) != NULL) {                                                         \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != NULL");             \
+        report(LOCATION "%s", #a " != NULL");                                  \
     }
 
-#define ASSERT_NOT_NULL(a)                                                     \
+#define CHECK_NOT_NULL(report, a)                                              \
     if ((a) == NULL) {                                                         \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " == NULL");             \
+        report(LOCATION "%s", #a " == NULL");                                  \
     }
-    
-#define ASSERT_FALSE(a)                                                        \
+
+#define CHECK_FALSE(report, a)                                                 \
     if (a) {                                                                   \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " is not false");        \
+        report(LOCATION "%s", #a " is not false");                             \
     }
 
-#define ASSERT_FLOAT_EQ(a, b)                                                  \
-    if (fabs((a) - (b)) > 1e-6) {                                              \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != " #b);              \
-    }
+#define CHECK_FLOAT_NEAR(report, a, b, tolerance)                              \
+    do {                                                                       \
+        double actual_ = (a), expected_ = (b);                                 \
+        if (!(fabs(actual_ - expected_) <= (tolerance))) {                     \
+            report(LOCATION "expected " #a " == " #b " within " #tolerance     \
+                   ", got %g and %g", actual_, expected_);                     \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_EQ_INT(report, a, b)                                             \
+    do {                                                                       \
+        long long actual_ = (a), expected_ = (b);                              \
+        if (actual_ != expected_) {                                            \
+            report(LOCATION "expected " #a " == " #b ", got %lld and %lld",    \
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_EQ_UINT(report, a, b)                                            \
+    do {                                                                       \
+        unsigned long long actual_ = (a), expected_ = (b);                     \
+        if (actual_ != expected_) {                                            \
+            report(LOCATION "expected " #a " == " #b ", got %llu and %llu",    \
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_EQ_PTR(report, a, b)                                             \
+    do {                                                                       \
+        const void *actual_ = (a), *expected_ = (b);                           \
+        if (actual_ != expected_) {                                            \
+            report(LOCATION "expected " #a " == " #b ", got %p and %p",        \
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_MEM_EQ(report, a, b, size)                                       \
+    do {                                                                       \
+        const unsigned char *actual_ = (const void *)(a);                      \
+        const unsigned char *expected_ = (const void *)(b);                    \
+        size_t size_ = (size);                                                 \
+        for (size_t i_ = 0; i_ < size_; i_++) {                                \
+            if (actual_[i_] != expected_[i_]) {                                \
+                report(LOCATION "expected " #a " == " #b " for %zu bytes, "    \
+                       "byte %zu is 0x%02x and 0x%02x",                        \
+                       size_, i_, actual_[i_], expected_[i_]);                 \
+                break;                                                         \
+            }                                                                  \
+        }                                                                      \
+    } while (0)
+
+/* Writes a value to a buffer of VALUE_STRING_SIZE bytes, formatted by its type. */
+#if defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L
+#define FORMAT_VALUE(buffer, x)                                                \
+    _Generic((x),                                                              \
+        _Bool: format_unsigned_value,                                          \
+        char: format_signed_value,                                             \
+        signed char: format_signed_value,                                      \
+        short: format_signed_value,                                            \
+        int: format_signed_value,                                              \
+        long: format_signed_value,                                             \
+        long long: format_signed_value,                                        \
+        unsigned char: format_unsigned_value,                                  \
+        unsigned short: format_unsigned_value,                                 \
+        unsigned int: format_unsigned_value,                                   \
+        unsigned long: format_unsigned_value,                                  \
+        unsigned long long: format_unsigned_value,                             \
+        float: format_float_value,                                             \
+        double: format_float_value,                                            \
+        long double: format_float_value,                                       \
+        default: format_pointer_value)(buffer, x)
+#else
+/* Before C11 the type of a value can not be told apart. */
+#define FORMAT_VALUE(buffer, x) strcpy(buffer, "?")
+#endif
+
+/* Compares two values of their own types, so pointers and 64 bit integers compare
+   exactly. */
+#define CHECK_COMPARE(report, a, op, b)                                        \
+    do {                                                                       \
+        __typeof__(a) actual_ = (a);                                           \
+        __typeof__(b) expected_ = (b);                                         \
+        if (!(actual_ op expected_)) {                                         \
+            char actual_string_[VALUE_STRING_SIZE];                            \
+            char expected_string_[VALUE_STRING_SIZE];                          \
+            FORMAT_VALUE(actual_string_, actual_);                             \
+            FORMAT_VALUE(expected_string_, expected_);                         \
+            report(LOCATION "expected " #a " " #op " " #b ", got %s and %s",   \
+                   actual_string_, expected_string_);                          \
+        }                                                                      \
+    } while (0)
+
+#define ASSERT(cond) CHECK(failf, cond)
+#define ASSERT_EQ(a, b) CHECK_EQ(failf, a, b)
+#define ASSERT_STRING_EQ(a, b) CHECK_STRING_EQ(failf, a, b)
+#define ASSERT_NULL(a) CHECK_NULL(failf, a)
+#define ASSERT_NOT_NULL(a) CHECK_NOT_NULL(failf, a)
+#define ASSERT_FALSE(a) CHECK_FALSE(failf, a)
+#define ASSERT_FLOAT_EQ(a, b) CHECK_FLOAT_NEAR(failf, a, b, 1e-6)
+#define ASSERT_FLOAT_NEAR(a, b, tolerance) CHECK_FLOAT_NEAR(failf, a, b, tolerance)
+#define ASSERT_EQ_INT(a, b) CHECK_EQ_INT(failf, a, b)
+#define ASSERT_EQ_UINT(a, b) CHECK_EQ_UINT(failf, a, b)
+#define ASSERT_EQ_PTR(a, b) CHECK_EQ_PTR(failf, a, b)
+#define ASSERT_MEM_EQ(a, b, size) CHECK_MEM_EQ(failf, a, b, size)
+#define ASSERT_NE(a, b) CHECK_COMPARE(failf, a, !=, b)
+#define ASSERT_LT(a, b) CHECK_COMPARE(failf, a, <, b)
+#define ASSERT_LE(a, b) CHECK_COMPARE(failf, a, <=, b)
+#define ASSERT_GT(a, b) CHECK_COMPARE(failf, a, >, b)
+#define ASSERT_GE(a, b) CHECK_COMPARE(failf, a, >=, b)
+
+#define EXPECT(cond) CHECK(expectf, cond)
+#define EXPECT_EQ(a, b) CHECK_EQ(expectf, a, b)
+#define EXPECT_STRING_EQ(a, b) CHECK_STRING_EQ(expectf, a, b)
+#define EXPECT_NULL(a) CHECK_NULL(expectf, a)
+#define EXPECT_NOT_NULL(a) CHECK_NOT_NULL(expectf, a)
+#define EXPECT_FALSE(a) CHECK_FALSE(expectf, a)
+#define EXPECT_FLOAT_EQ(a, b) CHECK_FLOAT_NEAR(expectf, a, b, 1e-6)
+#define EXPECT_FLOAT_NEAR(a, b, tolerance) CHECK_FLOAT_NEAR(expectf, a, b, tolerance)
+#define EXPECT_EQ_INT(a, b) CHECK_EQ_INT(expectf, a, b)
+#define EXPECT_EQ_UINT(a, b) CHECK_EQ_UINT(expectf, a, b)
+#define EXPECT_EQ_PTR(a, b) CHECK_EQ_PTR(expectf, a, b)
+#define EXPECT_MEM_EQ(a, b, size) CHECK_MEM_EQ(expectf, a, b, size)
+#define EXPECT_NE(a, b) CHECK_COMPARE(expectf, a, !=, b)
+#define EXPECT_LT(a, b) CHECK_COMPARE(expectf, a, <, b)
+#define EXPECT_LE(a, b) CHECK_COMPARE(expectf, a, <=, b)
+#define EXPECT_GT(a, b) CHECK_COMPARE(expectf, a, >, b)
+#define EXPECT_GE(a, b) CHECK_COMPARE(expectf, a, >=, b)
 
 typedef void test_fn();
 
 struct Test {
     const char *name;
     test_fn *test;
+    test_fn *setup;
+    test_fn *teardown;
 };
 
-void fail(char *msg);
+#if defined(__GNUC__)
+#define PRINTF_FORMAT __attribute__((format(printf, 1, 2)))
+#else
+#define PRINTF_FORMAT
+#endif
+
+void fail(const char *msg);
+PRINTF_FORMAT void failf(const char *format, ...);
+PRINTF_FORMAT void expectf(const char *format, ...);
+void reset_failures(void);
+void print_failures(const char *prefix);
+int run_step(test_fn *step);
+int run_test(struct Test *test);
+int run_suite_test(struct Test *suite, struct Test *test);
+void format_signed_value(char *buffer, long long value);
+void format_unsigned_value(char *buffer, unsigned long long value);
+void format_float_value(char *buffer, long double value);
+void format_pointer_value(char *buffer, const void *value);
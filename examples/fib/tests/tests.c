@@ -1,25 +1,63 @@
 #include "test_framework.h"
 
+#if !defined(TEST_FRAMEWORK_VERSION) || TEST_FRAMEWORK_VERSION != 1
+#error "test_framework.h does not match this c-builder version, delete it to regenerate it"
+#endif
+
+#undef TEST_FILE_ID
+#define TEST_FILE_ID 0
 #include "fib.c"
 
 #include <stdio.h>
+#include <string.h>
 #include <setjmp.h>
 
 extern jmp_buf jmpbuf;
-extern char *error_msg;
+extern char error_msg[];
 
 struct Test tests[] = {
-{ "fib", test_fib },
+{ "fib", test_fib, NULL, NULL },
 };
 
-int main() {
+struct Test suite = { "suite", NULL, NULL, NULL };
+
+int main(int argc, char **argv) {
+if (argc > 1) {
 for (int i = 0; i < 1; i++) {
-if (setjmp(jmpbuf) == 0) {
-tests[i].test();
+if (strcmp(argv[1], tests[i].name) != 0) {
+continue;
+}
+if (run_suite_test(&suite, &tests[i]) == 0) {
+return 0;
+}
+printf("\n");
+print_failures("c-builder-failure: ");
+return 1;
+}
+fprintf(stderr, "No test named `%s`\n", argv[1]);
+return 2;
+}
+
+int failed = 0;
+int suite_failed = 0;
+if (run_step(suite.setup)) {
+printf("Suite setup failed: %s\n", error_msg);
+failed = 1;
+} else {
+for (int i = 0; i < 1; i++) {
+if (run_test(&tests[i]) == 0) {
 printf("Test `%s` passed\n", tests[i].name);
 } else {
 printf("Test `%s` failed: %s\n", tests[i].name, error_msg);
+failed++;
 }
 }
-return 0;
+}
+reset_failures();
+if (run_step(suite.teardown)) {
+printf("Suite teardown failed: %s\n", error_msg);
+suite_failed = 1;
+}
+printf("\n%d passed; %d failed\n", 1 - failed, failed);
+return failed != 0 || suite_failed;
 }
@@ -1,59 +1,144 @@
-use crate::cli::{Build, Test};
-use crate::command;
-use crate::includes::{get_includes, Include, IncludeType};
+use crate::cli::Build;
+use crate::command::Command;
+use crate::compdb;
+use crate::depfile;
+use crate::fingerprint;
+use crate::includes::{get_includes_from_entry, Include, IncludeType, ScanOptions};
+use crate::library::{self, Library};
+use crate::pkg_config::{self, PkgConfig};
+use crate::preprocessor;
+use crate::sources;
+use crate::toolchain::{self, get_cc, get_dialect, get_ld, Toolchain};
 
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, Clone, Copy)]
-pub enum Mode {
-    Debug,
-    Release,
-}
+const BUILT_IN_PROFILES: [&str; 3] = ["debug", "release", "benchmark"];
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 pub struct Config {
-    pub mode: Option<Mode>,
+    #[serde(skip)]
+    pub profile: String,
     pub benchmark: Option<bool>,
     pub package: Package,
     pub debug: BuildArgs,
     pub release: BuildArgs,
+    #[serde(default, rename = "profile")]
+    pub custom_profiles: BTreeMap<String, Profile>,
+    #[serde(skip)]
+    pub profiles: BTreeMap<String, BuildArgs>,
     pub memory: Memory,
+    #[serde(default)]
+    pub test: TestConfig,
+    #[serde(default)]
+    pub toolchain: Toolchain,
+    pub lib: Option<Library>,
+    #[serde(default)]
+    pub bin: Vec<Binary>,
+    #[serde(skip)]
+    pub pkg_config: PkgConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Binary {
+    pub name: String,
+    pub path: String,
 }
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Package {
     pub name: String,
-    #[allow(dead_code)]
-    version: String,
-    #[allow(dead_code)]
-    authors: Vec<String>,
-    src: String,
-    benchmark: String,
+    pub version: String,
+    pub src: String,
+    pub benchmark: String,
+    #[serde(default)]
+    pub compile_commands: bool,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub libs: Vec<String>,
+    #[serde(default)]
+    pub lib_dirs: Vec<String>,
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    #[serde(default)]
+    pub pkg_config: Vec<String>,
+    #[serde(default)]
+    pub cflags: Vec<String>,
+    #[serde(default)]
+    pub ldflags: Vec<String>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, Define>,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Default, Clone)]
 pub struct BuildArgs {
-    debug: bool,
-    optimization: u8,
-    warnings: bool,
-    pedantic: bool,
-    std: String,
-    asm: Option<bool>,
+    pub debug: bool,
+    pub optimization: u8,
+    pub warnings: bool,
+    pub pedantic: bool,
+    pub std: String,
+    pub asm: Option<bool>,
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    #[serde(default)]
+    pub cflags: Vec<String>,
+    #[serde(default)]
+    pub ldflags: Vec<String>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, Define>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Profile {
+    pub inherits: String,
+    pub debug: Option<bool>,
+    pub optimization: Option<u8>,
+    pub warnings: Option<bool>,
+    pub pedantic: Option<bool>,
+    pub std: Option<String>,
+    #[serde(default)]
+    pub include_dirs: Vec<String>,
+    #[serde(default)]
+    pub cflags: Vec<String>,
+    #[serde(default)]
+    pub ldflags: Vec<String>,
+    #[serde(default)]
+    pub defines: BTreeMap<String, Define>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Define {
+    Flag(bool),
+    Integer(i64),
+    Value(String),
+}
+
+#[derive(Debug, Deserialize, Default)]
 pub struct Memory {
     pub leak_check: String,
     pub show_leak_kinds: String,
     pub track_origins: bool,
 }
 
+#[derive(Debug, Deserialize, Default)]
+pub struct TestConfig {
+    /// Milliseconds a test may run before it is killed, `0` disables the timeout.
+    pub timeout: Option<u64>,
+    /// Builds a test binary for every test file instead of one for all of them.
+    #[serde(default)]
+    pub per_file: bool,
+}
+
 pub fn get_build_options(build: &Build) -> Result<Config, String> {
     let toml = match fs::read_to_string("c-build.toml") {
         Ok(toml) => toml,
@@ -64,155 +149,435 @@ pub fn get_build_options(build: &Build) -> Result<Config, String> {
         Err(e) => return Err(format!("Failed to parse toml: {}", e)),
     };
 
-    config.mode = Some(match build.release {
-        true => Mode::Release,
-        false => Mode::Debug,
-    });
+    config.profile = match &build.profile {
+        Some(profile) => profile.clone(),
+        None if build.benchmark => "benchmark".to_string(),
+        None if build.release => "release".to_string(),
+        None => "debug".to_string(),
+    };
+    config.benchmark = Some(build.benchmark || config.profile == "benchmark");
+
+    validate_profile_name(&config.profile)?;
+    resolve_profiles(&mut config)?;
+    if !config.profiles.contains_key(&config.profile) {
+        return Err(format!("Profile `{}` is not defined", config.profile));
+    }
 
-    config.benchmark = Some(build.benchmark);
-    if build.benchmark {
-        config.mode = Some(Mode::Release);
+    for profile in config.profiles.values_mut() {
+        profile.asm = Some(build.asm);
     }
 
-    config.debug.asm = Some(build.asm);
-    config.release.asm = Some(build.asm);
+    toolchain::apply_env(&mut config.toolchain);
+
+    config.pkg_config = pkg_config::resolve(&config.package.pkg_config)?;
+
+    for (index, binary) in config.bin.iter().enumerate() {
+        if config.bin[..index]
+            .iter()
+            .any(|other| other.name == binary.name)
+        {
+            return Err(format!(
+                "Binary `{}` is defined more than once",
+                binary.name
+            ));
+        }
+    }
 
     Ok(config)
 }
 
-fn get_cflags(config: &Config) -> String {
-    let mut cflags = String::new();
-    let build = match config.mode.unwrap() {
-        Mode::Debug => &config.debug,
-        Mode::Release => &config.release,
+fn resolve_profile(
+    name: &str,
+    config: &Config,
+    resolving: &mut Vec<String>,
+) -> Result<BuildArgs, String> {
+    match name {
+        "debug" => return Ok(config.debug.clone()),
+        "release" | "benchmark" => return Ok(config.release.clone()),
+        _ => (),
+    }
+
+    let profile = match config.custom_profiles.get(name) {
+        Some(profile) => profile,
+        None => return Err(format!("Profile `{}` is not defined", name)),
     };
-    cflags.push_str(&format!("-O{} ", build.optimization));
+    if resolving.iter().any(|other| other == name) {
+        return Err(format!(
+            "Profile `{}` inherits from itself: {} -> {}",
+            name,
+            resolving.join(" -> "),
+            name
+        ));
+    }
+    resolving.push(name.to_string());
+
+    let mut args = resolve_profile(&profile.inherits, config, resolving)?;
+    if let Some(debug) = profile.debug {
+        args.debug = debug;
+    }
+    if let Some(optimization) = profile.optimization {
+        args.optimization = optimization;
+    }
+    if let Some(warnings) = profile.warnings {
+        args.warnings = warnings;
+    }
+    if let Some(pedantic) = profile.pedantic {
+        args.pedantic = pedantic;
+    }
+    if let Some(std) = &profile.std {
+        args.std = std.clone();
+    }
+    args.include_dirs
+        .extend(profile.include_dirs.iter().cloned());
+    args.cflags.extend(profile.cflags.iter().cloned());
+    args.ldflags.extend(profile.ldflags.iter().cloned());
+    for (name, define) in &profile.defines {
+        args.defines.insert(name.clone(), define.clone());
+    }
+
+    Ok(args)
+}
+
+/// Profiles name their directory in the target, so they must be a single path component.
+fn validate_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(format!("Profile `{}` is not a valid name", name));
+    }
+    Ok(())
+}
+
+/// Fills `config.profiles` with the built in profiles and every `[profile.<name>]`
+/// table, resolved through its `inherits` chain.
+pub fn resolve_profiles(config: &mut Config) -> Result<(), String> {
+    let mut profiles = BTreeMap::new();
+    profiles.insert("debug".to_string(), config.debug.clone());
+    profiles.insert("release".to_string(), config.release.clone());
+    // The benchmarks are always built with the release settings.
+    profiles.insert("benchmark".to_string(), config.release.clone());
+
+    for name in config.custom_profiles.keys() {
+        validate_profile_name(name)?;
+        if BUILT_IN_PROFILES.contains(&name.as_str()) {
+            return Err(format!(
+                "Profile `{}` is built in and cannot be redefined in `[profile.{}]`",
+                name, name
+            ));
+        }
+        profiles.insert(
+            name.clone(),
+            resolve_profile(name, config, &mut Vec::new())?,
+        );
+    }
+
+    config.profiles = profiles;
+    Ok(())
+}
+
+pub fn get_binaries(config: &Config) -> Vec<Binary> {
+    if config.benchmark.unwrap() {
+        return vec![Binary {
+            name: "benchmark".to_string(),
+            path: format!("{}/main.c", config.package.benchmark),
+        }];
+    }
+    get_package_binaries(config)
+}
+
+fn get_package_binaries(config: &Config) -> Vec<Binary> {
+    if !config.bin.is_empty() {
+        return config.bin.clone();
+    }
+
+    let main_file = format!("{}/main.c", config.package.src);
+    if config.lib.is_some() && !Path::new(&main_file).exists() {
+        return Vec::new();
+    }
+    vec![Binary {
+        name: config.package.name.clone(),
+        path: main_file,
+    }]
+}
+
+/// Returns the objects to link into a binary with the given entry file. When `sources` is
+/// set these are the matching files, otherwise the sources found through the includes of
+/// the entry.
+pub fn get_includes(
+    config: &Config,
+    entry: &Path,
+    options: &ScanOptions,
+) -> Result<Vec<Include>, String> {
+    if config.package.sources.is_empty() {
+        return get_includes_from_entry(entry, options);
+    }
+
+    let mut entries = vec![PathBuf::from(&config.package.benchmark).join("main.c")];
+    entries.extend(
+        get_package_binaries(config)
+            .into_iter()
+            .map(|binary| PathBuf::from(binary.path)),
+    );
+    let entries: Vec<PathBuf> = entries
+        .iter()
+        .filter_map(|entry| entry.canonicalize().ok())
+        .collect();
+    let tests = Path::new("tests").canonicalize().ok();
+
+    let mut includes = Vec::new();
+    for source in sources::find_sources(&config.package.sources, &config.package.exclude)? {
+        let source = match source.canonicalize() {
+            Ok(source) => source,
+            Err(e) => {
+                return Err(format!(
+                    "Failed to find source `{}`: {}",
+                    source.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+        let is_test = tests
+            .as_ref()
+            .is_some_and(|tests| source.starts_with(tests));
+        if !entries.contains(&source) && !is_test {
+            includes.push(Include {
+                kind: IncludeType::Local(source),
+            });
+        }
+    }
+    Ok(includes)
+}
+
+pub fn select_binaries(config: &Config, name: &Option<String>) -> Result<Vec<Binary>, String> {
+    let binaries = get_binaries(config);
+    match name {
+        None => Ok(binaries),
+        Some(name) => match binaries.iter().find(|binary| &binary.name == name) {
+            Some(binary) => Ok(vec![binary.clone()]),
+            None => Err(format!(
+                "No binary named `{}`, available binaries: {}",
+                name,
+                binaries
+                    .iter()
+                    .map(|binary| binary.name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            )),
+        },
+    }
+}
+
+pub fn get_build_args(config: &Config) -> &BuildArgs {
+    &config.profiles[&config.profile]
+}
+
+/// Package defines merged with the ones of the current mode, which take precedence.
+/// Defines set to `false` are left out.
+pub fn get_defines(config: &Config) -> BTreeMap<String, Option<String>> {
+    let mut defines = BTreeMap::new();
+    for (name, define) in config
+        .package
+        .defines
+        .iter()
+        .chain(get_build_args(config).defines.iter())
+    {
+        match define {
+            Define::Flag(true) => defines.insert(name.clone(), None),
+            Define::Flag(false) => defines.remove(name),
+            Define::Integer(value) => defines.insert(name.clone(), Some(value.to_string())),
+            Define::Value(value) => defines.insert(name.clone(), Some(value.clone())),
+        };
+    }
+    defines
+}
+
+/// Package include directories followed by the ones of the current mode, in the order
+/// they are searched.
+pub fn get_include_dirs(config: &Config) -> Vec<&String> {
+    config
+        .package
+        .include_dirs
+        .iter()
+        .chain(get_build_args(config).include_dirs.iter())
+        .collect()
+}
+
+/// Returns what the include scanner needs to resolve headers like the compiler. Like
+/// `-DNAME` on the command line, a define without a value expands to `1`.
+pub fn get_scan_options(config: &Config) -> ScanOptions {
+    let mut defines = preprocessor::get_predefined();
+    for (name, value) in get_defines(config) {
+        defines.insert(name, Some(value.unwrap_or_else(|| "1".to_string())));
+    }
+    ScanOptions {
+        defines,
+        include_dirs: get_include_dirs(config)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        src: PathBuf::from(&config.package.src),
+    }
+}
+
+/// The flags to compile the sources with, `pic` selects position independent code for
+/// shared libraries.
+pub fn get_cflags(config: &Config, pic: bool) -> Vec<String> {
+    let mut cflags = Vec::new();
+    let build = get_build_args(config);
+    cflags.push(format!("-O{}", build.optimization));
     if build.debug {
-        cflags.push_str("-g ");
+        cflags.push("-g".to_string());
     }
     if build.warnings {
-        cflags.push_str("-Wall ");
+        cflags.push("-Wall".to_string());
     }
     if build.pedantic {
-        cflags.push_str("-pedantic ");
+        cflags.push("-pedantic".to_string());
     }
-    cflags.push_str(&format!("-std={} ", build.std));
+    cflags.push(toolchain::get_std_flag(
+        get_dialect(&config.toolchain),
+        &build.std,
+    ));
 
     if config.benchmark.unwrap() {
-        cflags.push_str("-pg ");
+        cflags.push("-pg".to_string());
     }
 
     if build.asm == Some(true) {
-        cflags.push_str("-S ");
+        cflags.push("-S".to_string());
     }
 
+    if pic {
+        cflags.push("-fPIC".to_string());
+    }
+
+    for dir in get_include_dirs(config) {
+        cflags.push(format!("-I{}", dir));
+    }
+    cflags.extend(config.pkg_config.cflags.iter().cloned());
+
+    for (name, value) in get_defines(config) {
+        match value {
+            Some(value) => cflags.push(format!("-D{}={}", name, value)),
+            None => cflags.push(format!("-D{}", name)),
+        }
+    }
+    cflags.extend(config.package.cflags.iter().cloned());
+    cflags.extend(build.cflags.iter().cloned());
+
     cflags
 }
 
+pub fn get_link_args(config: &Config) -> Vec<String> {
+    let mut args = Vec::new();
+    args.extend(config.package.ldflags.iter().cloned());
+    args.extend(get_build_args(config).ldflags.iter().cloned());
+    for dir in &config.package.lib_dirs {
+        args.push(format!("-L{}", dir));
+    }
+    for lib in &config.package.libs {
+        args.push(format!("-l{}", lib));
+    }
+    args.extend(config.pkg_config.libs.iter().cloned());
+    args.push("-lm".to_string());
+    args
+}
+
 pub fn get_target(config: &Config) -> String {
     match config.benchmark.unwrap() {
-        true => "c_target/benchmark",
-        false => match config.mode.unwrap() {
-            Mode::Debug => "c_target/debug",
-            Mode::Release => "c_target/release",
-        },
+        true => "c_target/benchmark".to_string(),
+        false => format!("c_target/{}", config.profile),
     }
-    .to_string()
 }
 
-fn get_object_name(include: &Include) -> String {
+/// The path of the outputs of a source relative to the output directory, without an
+/// extension. Sources outside of the project are named after their stem and a hash.
+fn get_source_name(include: &Include) -> String {
     match &include.kind {
         IncludeType::Local(path) => {
-            path.file_name()
-                .and_then(|name| name.to_str())
-                .map(|name| {
+            let source = path.with_extension("c");
+            let relative = if source.is_absolute() {
+                std::env::current_dir()
+                    .and_then(|dir| dir.canonicalize())
+                    .ok()
+                    .and_then(|dir| source.strip_prefix(dir).ok().map(|path| path.to_path_buf()))
+            } else {
+                Some(source.clone())
+            };
+
+            match relative {
+                Some(relative)
+                    if relative
+                        .components()
+                        .all(|component| matches!(component, Component::Normal(_))) =>
+                {
+                    relative.with_extension("").to_string_lossy().to_string()
+                }
+                _ => {
                     let mut hasher = DefaultHasher::new();
-                    name.hash(&mut hasher);
-                    hasher.finish().to_string()
-                })
-                .unwrap()
-                + ".o"
+                    source.hash(&mut hasher);
+                    format!(
+                        "external/{}-{}",
+                        source.file_stem().unwrap().to_string_lossy(),
+                        hasher.finish()
+                    )
+                }
+            }
         }
         IncludeType::System => unreachable!(),
     }
 }
 
+fn get_object_name(include: &Include) -> String {
+    format!("{}.o", get_source_name(include))
+}
+
 fn get_asm_name(include: &Include) -> String {
-    match &include.kind {
-        IncludeType::Local(path) => {
-            path.file_name()
-                .and_then(|name| name.to_str())
-                .map(|name| {
-                    let mut hasher = DefaultHasher::new();
-                    name.hash(&mut hasher);
-                    hasher.finish().to_string()
-                })
-                .unwrap()
-                + ".s"
-        }
-        IncludeType::System => unreachable!(),
-    }
+    format!("{}.s", get_source_name(include))
 }
 
 pub fn generate_build_command(
     includes: &Vec<Include>,
     config: &Config,
     main_file: &str,
-    test: Option<&Test>,
-) -> String {
-    let build = match config.mode.unwrap() {
-        Mode::Debug => &config.debug,
-        Mode::Release => &config.release,
-    };
-    let mut command = format!("gcc {}{} ", &get_cflags(config), main_file);
+    output: &str,
+) -> Command {
+    let build = get_build_args(config);
+    let mut command = Command::new(get_ld(&config.toolchain));
+    command.args(get_cflags(config, false)).arg(main_file);
     if !build.asm.unwrap() {
         for include in includes {
             match &include.kind {
                 IncludeType::Local(_) => match build.asm {
-                    Some(true) => command.push_str(&format!(
-                        "{}/asm/{} ",
+                    Some(true) => command.arg(format!(
+                        "{}/asm/{}",
                         get_target(config),
                         get_asm_name(include)
                     )),
-                    Some(false) => command.push_str(&format!(
-                        "{}/obj/{} ",
+                    Some(false) => command.arg(format!(
+                        "{}/obj/{}",
                         get_target(config),
                         get_object_name(include)
                     )),
                     _ => unreachable!(),
                 },
-                IncludeType::System => (),
-            }
+                IncludeType::System => &mut command,
+            };
         }
     }
 
-    command.push_str(&format!(
-        "-o {}/{}{} ",
+    command.arg("-o").arg(format!(
+        "{}/{}{}",
         get_target(config),
-        if let Some(test) = test {
-            if let Some(single) = &test.single {
-                single
-            } else {
-                "test"
-            }
-        } else if config.benchmark.unwrap() {
-            "benchmark"
-        } else {
-            &config.package.name
-        },
+        output,
         if build.asm.unwrap() { ".s" } else { "" }
     ));
 
-    command.push_str("-lm");
+    command.args(get_link_args(config));
 
     command
 }
 
 pub fn create_output_directory(config: &Config) -> Result<(), String> {
-    let build = match config.mode.unwrap() {
-        Mode::Debug => &config.debug,
-        Mode::Release => &config.release,
-    };
+    let build = get_build_args(config);
     let path = std::path::PathBuf::from(get_target(config).clone());
     if !path.exists() {
         match fs::create_dir_all(path) {
@@ -235,14 +600,71 @@ pub fn create_output_directory(config: &Config) -> Result<(), String> {
     }
 }
 
-fn should_build(include: &mut Include, config: &Config) -> Result<bool, String> {
-    let build = match config.mode.unwrap() {
-        Mode::Debug => &config.debug,
-        Mode::Release => &config.release,
-    };
+fn get_output_dir(config: &Config, pic: bool) -> PathBuf {
+    let build = get_build_args(config);
+    PathBuf::from(get_target(config)).join(if build.asm.unwrap() {
+        "asm"
+    } else if pic {
+        "pic"
+    } else {
+        "obj"
+    })
+}
+
+fn get_output_path(include: &Include, config: &Config, pic: bool) -> PathBuf {
+    let build = get_build_args(config);
+    if build.asm.unwrap() {
+        get_output_dir(config, pic).join(get_asm_name(include))
+    } else {
+        get_object_path(include, config, pic)
+    }
+}
+
+pub fn get_object_path(include: &Include, config: &Config, pic: bool) -> PathBuf {
+    PathBuf::from(get_target(config))
+        .join(if pic { "pic" } else { "obj" })
+        .join(get_object_name(include))
+}
+
+fn get_depfile_path(include: &Include, config: &Config, pic: bool) -> PathBuf {
+    get_output_dir(config, pic).join(format!("{}.d", get_source_name(include)))
+}
+
+fn get_fingerprint_path(include: &Include, config: &Config, pic: bool) -> PathBuf {
+    get_output_dir(config, pic).join(format!("{}.fingerprint", get_source_name(include)))
+}
+
+fn get_object_fingerprint(
+    include: &Include,
+    config: &Config,
+    pic: bool,
+    compiler_id: &str,
+) -> String {
+    match &include.kind {
+        IncludeType::Local(path) => fingerprint::get_fingerprint(
+            compiler_id,
+            &get_cflags(config, pic),
+            &path.with_extension("c"),
+        ),
+        IncludeType::System => unreachable!(),
+    }
+}
+
+fn should_build(
+    include: &mut Include,
+    config: &Config,
+    pic: bool,
+    compiler_id: &str,
+) -> Result<bool, String> {
     match &include.kind {
         IncludeType::System => return Ok(false),
         IncludeType::Local(path) => {
+            if !fingerprint::is_current(
+                &get_fingerprint_path(include, config, pic),
+                &get_object_fingerprint(include, config, pic, compiler_id),
+            ) {
+                return Ok(true);
+            }
             let path = std::path::PathBuf::from(&config.package.src).join(path);
             let metadata = match fs::metadata(match path.with_extension("c").canonicalize() {
                 Ok(path) => path,
@@ -252,18 +674,16 @@ fn should_build(include: &mut Include, config: &Config) -> Result<bool, String>
                 Err(e) => return Err(format!("Failed to fetch metadata for file: {}", e)),
             };
             if let Ok(created_time) = metadata.modified() {
-                match fs::metadata(if build.asm.unwrap() {
-                    PathBuf::from(get_target(config))
-                        .join("asm")
-                        .join(get_asm_name(include))
-                } else {
-                    PathBuf::from(get_target(config))
-                        .join("obj")
-                        .join(get_object_name(include))
-                }) {
+                match fs::metadata(get_output_path(include, config, pic)) {
                     Ok(metadata) => {
                         if let Ok(obj_created_time) = metadata.modified() {
-                            return Ok(created_time > obj_created_time);
+                            if created_time > obj_created_time {
+                                return Ok(true);
+                            }
+                            return depfile::is_outdated(
+                                &get_depfile_path(include, config, pic),
+                                obj_created_time,
+                            );
                         }
                     }
                     Err(e) => match e.kind() {
@@ -277,95 +697,109 @@ fn should_build(include: &mut Include, config: &Config) -> Result<bool, String>
     Ok(true)
 }
 
-fn build_object(include: &mut Include, config: &Config) -> Result<(), String> {
-    if !should_build(include, config)? {
+fn build_object(
+    include: &mut Include,
+    config: &Config,
+    pic: bool,
+    compiler_id: &str,
+) -> Result<(), String> {
+    if !should_build(include, config, pic, compiler_id)? {
         return Ok(());
     }
-    let build = match config.mode.unwrap() {
-        Mode::Debug => &config.debug,
-        Mode::Release => &config.release,
-    };
 
-    let command = match &include.kind {
-        IncludeType::Local(path) => format!(
-            "gcc -fdiagnostics-color=always {} -c {} -o {}/{}/{}",
-            get_cflags(config),
-            path.with_extension("c").to_str().unwrap(),
-            get_target(config),
-            if build.asm.unwrap() { "asm" } else { "obj" },
-            if build.asm.unwrap() {
-                get_asm_name(include)
-            } else {
-                get_object_name(include)
-            },
-        ),
-        IncludeType::System => "".to_string(),
+    if let Some(parent) = get_output_path(include, config, pic).parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Err(format!("Failed to create output directory: {}", e));
+        }
+    }
+
+    let source = match &include.kind {
+        IncludeType::Local(path) => path.with_extension("c"),
+        IncludeType::System => return Ok(()),
     };
 
-    match command::output(&command) {
-        Ok(status) => {
-            if !status.success() {
-                Err("Failed to build object file: {}".to_string())
-            } else {
-                Ok(())
-            }
-        }
-        Err(e) => Err(format!("Failed to run command: {}", e)),
+    let mut command = Command::new(get_cc(&config.toolchain));
+    command
+        .arg(toolchain::get_color_flag(get_dialect(&config.toolchain)))
+        .args(get_cflags(config, pic))
+        .arg("-MMD")
+        .arg("-MF")
+        .arg(get_depfile_path(include, config, pic))
+        .arg("-c")
+        .arg(&source)
+        .arg("-o")
+        .arg(get_output_path(include, config, pic));
+
+    if !command.status()?.success() {
+        Err(format!(
+            "Failed to build object file for `{}`",
+            source.to_string_lossy()
+        ))
+    } else {
+        fingerprint::write(
+            &get_fingerprint_path(include, config, pic),
+            &get_object_fingerprint(include, config, pic, compiler_id),
+        )
     }
 }
 
-pub fn build_object_files(includes: &Vec<Include>, config: &Config) -> Result<(), String> {
+pub fn build_object_files(
+    includes: &Vec<Include>,
+    config: &Config,
+    pic: bool,
+) -> Result<(), String> {
+    let compiler_id = fingerprint::get_compiler_id(get_cc(&config.toolchain))?;
     includes
         .into_par_iter()
         .try_for_each(|include| match &include.kind {
-            IncludeType::Local(_path) => match build_object(&mut include.clone(), config) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(format!("Failed to build object files: {}", e)),
-            },
+            IncludeType::Local(_path) => {
+                match build_object(&mut include.clone(), config, pic, &compiler_id) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(format!("Failed to build object files: {}", e)),
+                }
+            }
             IncludeType::System => Ok(()),
         })
 }
 
 pub fn build(build: &Build) -> Result<String, String> {
-    let config: Config = match get_build_options(build) {
-        Ok(config) => config,
-        Err(e) => return Err(e),
-    };
-
-    let path = if build.benchmark {
-        std::path::PathBuf::from(&config.package.benchmark)
-    } else {
-        std::path::PathBuf::from(&config.package.src)
-    };
-    let includes = get_includes(path)?;
+    let config: Config = get_build_options(build)?;
 
     create_output_directory(&config)?;
-    build_object_files(&includes, &config)?;
-
-    let main_file = format!(
-        "{}/main.c",
-        if build.benchmark {
-            &config.package.benchmark
-        } else {
-            &config.package.src
-        }
-    );
 
-    let build_command = generate_build_command(&includes, &config, &main_file, None);
+    if !build.benchmark && !build.asm && build.bin.is_none() {
+        library::build_library(&config)?;
+    }
 
-    println!("Building {}/{}", get_target(&config), config.package.name);
+    let options = get_scan_options(&config);
+    let mut all_includes = Vec::new();
+    let mut binaries = Vec::new();
+    for binary in select_binaries(&config, &build.bin)? {
+        let includes = get_includes(&config, Path::new(&binary.path), &options)?;
+        all_includes.extend(includes.iter().cloned());
+        binaries.push((binary, includes));
+    }
+    all_includes.sort();
+    all_includes.dedup();
 
-    println!("Running command: {}", build_command);
-    match command::output(&build_command) {
-        Ok(status) => {
-            if !status.success() {
-                Err("Build not successful".to_string())
-            } else {
-                Ok("Build successful".to_string())
-            }
+    build_object_files(&all_includes, &config, false)?;
+
+    if config.package.compile_commands {
+        compdb::write_compdb(&config.profile)?;
+    }
+
+    for (binary, includes) in binaries {
+        let build_command = generate_build_command(&includes, &config, &binary.path, &binary.name);
+
+        println!("Building {}/{}", get_target(&config), binary.name);
+
+        println!("Running command: {}", build_command);
+        if !build_command.status()?.success() {
+            return Err("Build not successful".to_string());
         }
-        Err(e) => Err(format!("Failed to run command: {}", e)),
     }
+
+    Ok("Build successful".to_string())
 }
 
 #[cfg(test)]
@@ -377,16 +811,18 @@ mod tests {
         let build = Build {
             release: false,
             benchmark: false,
+            profile: None,
             asm: false,
+            bin: None,
         };
         let config = get_build_options(&build);
-        assert_eq!(config.is_ok(), false);
+        assert!(config.is_err());
     }
 
     #[test]
     fn test_get_cflags() {
-        let config = Config {
-            mode: Some(Mode::Debug),
+        let mut config = Config {
+            profile: "debug".to_string(),
             benchmark: Some(false),
             package: Package {
                 name: "test".to_string(),
@@ -400,6 +836,7 @@ mod tests {
                 pedantic: false,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             release: BuildArgs {
                 debug: false,
@@ -408,20 +845,27 @@ mod tests {
                 pedantic: false,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             memory: Memory {
                 leak_check: "".to_string(),
                 show_leak_kinds: "".to_string(),
                 track_origins: false,
             },
+            ..Default::default()
         };
-        assert_eq!(get_cflags(&config), "-O0 -g -std=c11 ");
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(get_cflags(&config, false), ["-O0", "-g", "-std=c11"]);
+        assert_eq!(
+            get_cflags(&config, true),
+            ["-O0", "-g", "-std=c11", "-fPIC"]
+        );
     }
 
     #[test]
     fn test_get_cflags_all() {
         let mut config = Config {
-            mode: Some(Mode::Debug),
+            profile: "debug".to_string(),
             benchmark: Some(false),
             package: Package {
                 name: "test".to_string(),
@@ -435,6 +879,7 @@ mod tests {
                 pedantic: true,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             release: BuildArgs {
                 debug: false,
@@ -443,22 +888,277 @@ mod tests {
                 pedantic: true,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             memory: Memory {
                 leak_check: "".to_string(),
                 show_leak_kinds: "".to_string(),
                 track_origins: false,
             },
+            ..Default::default()
+        };
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(
+            get_cflags(&config, false),
+            ["-O0", "-g", "-Wall", "-pedantic", "-std=c11"]
+        );
+        config.profile = "release".to_string();
+        assert_eq!(
+            get_cflags(&config, false),
+            ["-O3", "-Wall", "-pedantic", "-std=c11"]
+        );
+    }
+
+    #[test]
+    fn test_get_cflags_include_dirs() {
+        let mut config = Config {
+            profile: "debug".to_string(),
+            benchmark: Some(false),
+            package: Package {
+                include_dirs: vec!["include".to_string(), "vendor/zlib".to_string()],
+                ..Default::default()
+            },
+            debug: BuildArgs {
+                std: "c11".to_string(),
+                include_dirs: vec!["debug/include".to_string()],
+                ..Default::default()
+            },
+            pkg_config: PkgConfig {
+                cflags: vec!["-I/usr/include/curl".to_string()],
+                libs: vec!["-lcurl".to_string()],
+            },
+            ..Default::default()
+        };
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(
+            get_cflags(&config, false),
+            [
+                "-O0",
+                "-std=c11",
+                "-Iinclude",
+                "-Ivendor/zlib",
+                "-Idebug/include",
+                "-I/usr/include/curl"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_cflags_extra() {
+        let mut config = Config {
+            profile: "debug".to_string(),
+            benchmark: Some(false),
+            package: Package {
+                cflags: vec!["-Wextra".to_string()],
+                defines: BTreeMap::from([
+                    ("VERSION".to_string(), Define::Value("\"1.0\"".to_string())),
+                    ("LOGGING".to_string(), Define::Flag(true)),
+                    ("LEVEL".to_string(), Define::Integer(1)),
+                ]),
+                ..Default::default()
+            },
+            debug: BuildArgs {
+                std: "c11".to_string(),
+                cflags: vec!["-Werror".to_string(), "-march=native".to_string()],
+                defines: BTreeMap::from([
+                    ("LEVEL".to_string(), Define::Integer(3)),
+                    ("LOGGING".to_string(), Define::Flag(false)),
+                ]),
+                ..Default::default()
+            },
+            ..Default::default()
         };
-        assert_eq!(get_cflags(&config), "-O0 -g -Wall -pedantic -std=c11 ");
-        config.mode = Some(Mode::Release);
-        assert_eq!(get_cflags(&config), "-O3 -Wall -pedantic -std=c11 ");
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(
+            get_cflags(&config, false),
+            [
+                "-O0",
+                "-std=c11",
+                "-DLEVEL=3",
+                "-DVERSION=\"1.0\"",
+                "-Wextra",
+                "-Werror",
+                "-march=native"
+            ]
+        );
+
+        config.profile = "release".to_string();
+        config.release.std = "c11".to_string();
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(
+            get_cflags(&config, false),
+            [
+                "-O0",
+                "-std=c11",
+                "-DLEVEL=1",
+                "-DLOGGING",
+                "-DVERSION=\"1.0\"",
+                "-Wextra"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_link_args() {
+        let mut config = Config {
+            profile: "debug".to_string(),
+            ..Default::default()
+        };
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(get_link_args(&config), ["-lm"]);
+
+        config.package.libs = vec!["pthread".to_string(), "z".to_string()];
+        config.package.lib_dirs = vec!["/opt/lib".to_string()];
+        config.pkg_config.libs = vec!["-lcurl".to_string()];
+        assert_eq!(
+            get_link_args(&config),
+            ["-L/opt/lib", "-lpthread", "-lz", "-lcurl", "-lm"]
+        );
+
+        config.package.ldflags = vec!["-rdynamic".to_string()];
+        config.debug.ldflags = vec!["-fsanitize=address".to_string()];
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(
+            get_link_args(&config),
+            [
+                "-rdynamic",
+                "-fsanitize=address",
+                "-L/opt/lib",
+                "-lpthread",
+                "-lz",
+                "-lcurl",
+                "-lm"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_profiles() {
+        let mut config = Config {
+            profile: "asan".to_string(),
+            benchmark: Some(false),
+            release: BuildArgs {
+                optimization: 3,
+                warnings: true,
+                std: "c11".to_string(),
+                cflags: vec!["-flto".to_string()],
+                ..Default::default()
+            },
+            custom_profiles: BTreeMap::from([
+                (
+                    "profile".to_string(),
+                    Profile {
+                        inherits: "release".to_string(),
+                        debug: Some(true),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "asan".to_string(),
+                    Profile {
+                        inherits: "profile".to_string(),
+                        optimization: Some(1),
+                        cflags: vec!["-fsanitize=address".to_string()],
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        resolve_profiles(&mut config).unwrap();
+
+        assert_eq!(
+            get_cflags(&config, false),
+            [
+                "-O1",
+                "-g",
+                "-Wall",
+                "-std=c11",
+                "-flto",
+                "-fsanitize=address"
+            ]
+        );
+        assert_eq!(get_target(&config), "c_target/asan");
+        assert_eq!(config.profiles.len(), 5);
+        assert_eq!(config.profiles["benchmark"].optimization, 3);
+    }
+
+    #[test]
+    fn test_resolve_profiles_errors() {
+        let mut config = Config {
+            custom_profiles: BTreeMap::from([(
+                "a".to_string(),
+                Profile {
+                    inherits: "b".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_profiles(&mut config).unwrap_err(),
+            "Profile `b` is not defined"
+        );
+
+        config.custom_profiles.insert(
+            "b".to_string(),
+            Profile {
+                inherits: "a".to_string(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            resolve_profiles(&mut config).unwrap_err(),
+            "Profile `a` inherits from itself: a -> b -> a"
+        );
+
+        let mut config = Config {
+            custom_profiles: BTreeMap::from([(
+                "release".to_string(),
+                Profile {
+                    inherits: "debug".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_profiles(&mut config).unwrap_err(),
+            "Profile `release` is built in and cannot be redefined in `[profile.release]`"
+        );
+
+        let mut config = Config {
+            custom_profiles: BTreeMap::from([(
+                "../x".to_string(),
+                Profile {
+                    inherits: "debug".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_profiles(&mut config).unwrap_err(),
+            "Profile `../x` is not a valid name"
+        );
+    }
+
+    #[test]
+    fn test_validate_profile_name() {
+        assert!(validate_profile_name("ci").is_ok());
+        assert!(validate_profile_name("release-lto").is_ok());
+        for name in ["", ".", "..", "../x", "a/b", "a\\b", "/tmp"] {
+            assert_eq!(
+                validate_profile_name(name).unwrap_err(),
+                format!("Profile `{}` is not a valid name", name)
+            );
+        }
     }
 
     #[test]
     fn test_get_target() {
         let mut config = Config {
-            mode: Some(Mode::Debug),
+            profile: "debug".to_string(),
             benchmark: Some(false),
             package: Package {
                 name: "test".to_string(),
@@ -472,6 +1172,7 @@ mod tests {
                 pedantic: false,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             release: BuildArgs {
                 debug: false,
@@ -480,23 +1181,26 @@ mod tests {
                 pedantic: false,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             memory: Memory {
                 leak_check: "".to_string(),
                 show_leak_kinds: "".to_string(),
                 track_origins: false,
             },
+            ..Default::default()
         };
+        resolve_profiles(&mut config).unwrap();
 
         assert_eq!(get_target(&config), "c_target/debug");
-        config.mode = Some(Mode::Release);
+        config.profile = "release".to_string();
         assert_eq!(get_target(&config), "c_target/release");
 
-        config.mode = Some(Mode::Debug);
+        config.profile = "debug".to_string();
         config.benchmark = Some(true);
 
         assert_eq!(get_target(&config), "c_target/benchmark");
-        config.mode = Some(Mode::Release);
+        config.profile = "release".to_string();
         assert_eq!(get_target(&config), "c_target/benchmark");
     }
 
@@ -505,7 +1209,93 @@ mod tests {
         let include = Include {
             kind: IncludeType::Local(PathBuf::from("test.c")),
         };
-        assert_eq!(get_object_name(&include), "5868638564572808266.o");
+        assert_eq!(get_object_name(&include), "test.o");
+
+        let include = Include {
+            kind: IncludeType::Local(PathBuf::from("src/net/util.h")),
+        };
+        assert_eq!(get_object_name(&include), "src/net/util.o");
+        let include = Include {
+            kind: IncludeType::Local(PathBuf::from("src/fs/util.h")),
+        };
+        assert_eq!(get_object_name(&include), "src/fs/util.o");
+        let include = Include {
+            kind: IncludeType::Local(PathBuf::from("src/foo.bar.c")),
+        };
+        assert_eq!(get_object_name(&include), "src/foo.bar.o");
+    }
+
+    #[test]
+    fn test_get_object_name_external() {
+        let include = Include {
+            kind: IncludeType::Local(PathBuf::from("/nonexistent/lib/util.h")),
+        };
+        let name = get_object_name(&include);
+        assert!(name.starts_with("external/util-"));
+        assert!(name.ends_with(".o"));
+
+        let include = Include {
+            kind: IncludeType::Local(PathBuf::from("/nonexistent/lib/foo.bar.c")),
+        };
+        let name = get_object_name(&include);
+        assert!(name.starts_with("external/foo.bar-"));
+        assert!(name.ends_with(".o"));
+        assert_eq!(
+            get_asm_name(&include),
+            format!("{}.s", name.strip_suffix(".o").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_get_binaries() {
+        let mut config = Config {
+            profile: "debug".to_string(),
+            benchmark: Some(false),
+            package: Package {
+                name: "test".to_string(),
+                src: "src".to_string(),
+                benchmark: "benchmark".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        resolve_profiles(&mut config).unwrap();
+        assert_eq!(
+            get_binaries(&config),
+            [Binary {
+                name: "test".to_string(),
+                path: "src/main.c".to_string(),
+            }]
+        );
+
+        config.bin = vec![
+            Binary {
+                name: "server".to_string(),
+                path: "src/server.c".to_string(),
+            },
+            Binary {
+                name: "client".to_string(),
+                path: "src/client.c".to_string(),
+            },
+        ];
+        assert_eq!(get_binaries(&config), config.bin);
+        assert_eq!(
+            select_binaries(&config, &Some("client".to_string())).unwrap(),
+            [config.bin[1].clone()]
+        );
+        assert_eq!(
+            select_binaries(&config, &Some("other".to_string())).unwrap_err(),
+            "No binary named `other`, available binaries: server, client"
+        );
+
+        config.benchmark = Some(true);
+        assert_eq!(
+            get_binaries(&config),
+            [Binary {
+                name: "benchmark".to_string(),
+                path: "benchmark/main.c".to_string(),
+            }]
+        );
     }
 
     #[test]
@@ -518,8 +1308,8 @@ mod tests {
                 kind: IncludeType::Local(PathBuf::from("test2.c")),
             },
         ];
-        let config = Config {
-            mode: Some(Mode::Debug),
+        let mut config = Config {
+            profile: "debug".to_string(),
             benchmark: Some(false),
             package: Package {
                 name: "test".to_string(),
@@ -533,6 +1323,7 @@ mod tests {
                 pedantic: false,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             release: BuildArgs {
                 debug: false,
@@ -541,16 +1332,19 @@ mod tests {
                 pedantic: false,
                 std: "c11".to_string(),
                 asm: Some(false),
+                ..Default::default()
             },
             memory: Memory {
                 leak_check: "".to_string(),
                 show_leak_kinds: "".to_string(),
                 track_origins: false,
             },
+            ..Default::default()
         };
+        resolve_profiles(&mut config).unwrap();
         assert_eq!(
-            generate_build_command(&includes, &config, "src/main.c", None),
-            "gcc -O0 -g -std=c11 src/main.c c_target/debug/obj/5868638564572808266.o c_target/debug/obj/10537904563806491211.o -o c_target/debug/test -lm"
+            generate_build_command(&includes, &config, "src/main.c", "test").to_string(),
+            "gcc -O0 -g -std=c11 src/main.c c_target/debug/obj/test.o c_target/debug/obj/test2.o -o c_target/debug/test -lm"
         );
     }
 }
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -27,6 +27,10 @@ pub enum Commands {
     Init(Init),
     #[command(about, long_about = Some("Cleans the project"))]
     Clean,
+    #[command(about, long_about = Some("Writes compile_commands.json for the project"))]
+    Compdb(Compdb),
+    #[command(about, long_about = Some("Prints the include graph of the project"))]
+    Tree(Tree),
 }
 
 #[derive(Parser, Debug)]
@@ -47,8 +51,21 @@ pub struct Build {
         conflicts_with = "release"
     )]
     pub benchmark: bool,
+    #[arg(
+        short,
+        long,
+        help = "Builds with the given profile",
+        conflicts_with_all = ["release", "benchmark"]
+    )]
+    pub profile: Option<String>,
     #[arg(short, long, default_value_t = false, help = "Compile to assembly")]
     pub asm: bool,
+    #[arg(
+        long,
+        help = "Build only the given binary",
+        conflicts_with = "benchmark"
+    )]
+    pub bin: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -69,6 +86,15 @@ pub struct Run {
         conflicts_with = "release"
     )]
     pub benchmark: bool,
+    #[arg(
+        short,
+        long,
+        help = "Builds with the given profile",
+        conflicts_with_all = ["release", "benchmark"]
+    )]
+    pub profile: Option<String>,
+    #[arg(long, help = "Run the given binary", conflicts_with = "benchmark")]
+    pub bin: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -80,8 +106,96 @@ pub struct Test {
         help = "Run tests in release mode"
     )]
     pub release: bool,
+    #[arg(
+        short,
+        long,
+        help = "Run tests with the given profile",
+        conflicts_with = "release"
+    )]
+    pub profile: Option<String>,
     #[arg(short, long, help = "Run a specific test")]
     pub single: Option<String>,
+    #[arg(
+        long,
+        help = "Run the tests with names matching a glob pattern",
+        conflicts_with = "single"
+    )]
+    pub filter: Option<String>,
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        help = "Number of tests to run at once, 0 runs one per CPU core"
+    )]
+    pub jobs: usize,
+    #[arg(
+        long,
+        value_enum,
+        help = "Write a test report in the given format",
+        requires = "output"
+    )]
+    pub format: Option<ReportFormat>,
+    #[arg(
+        short,
+        long,
+        help = "File to write the test report to",
+        requires = "format"
+    )]
+    pub output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+pub enum ReportFormat {
+    Junit,
+    Tap,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+pub struct Compdb {
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        help = "Use the release mode flags"
+    )]
+    pub release: bool,
+    #[arg(
+        short,
+        long,
+        help = "Use the flags of the given profile",
+        conflicts_with = "release"
+    )]
+    pub profile: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+pub enum TreeFormat {
+    Text,
+    Dot,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+pub struct Tree {
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        help = "Use the release mode defines"
+    )]
+    pub release: bool,
+    #[arg(
+        short,
+        long,
+        help = "Use the defines of the given profile",
+        conflicts_with = "release"
+    )]
+    pub profile: Option<String>,
+    #[arg(long, value_enum, default_value_t = TreeFormat::Text, help = "Output format")]
+    pub format: TreeFormat,
+    #[arg(long, help = "Show the files that include the given header")]
+    pub invert: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -96,56 +210,142 @@ mod tests {
 
     #[test]
     fn test_build() {
-        let args = Cli::parse_from(&["c-builder", "build"]);
+        let args = Cli::parse_from(["c-builder", "build"]);
         assert!(matches!(args.command, Commands::Build(_)));
     }
 
     #[test]
     fn test_build_release() {
-        let args = Cli::parse_from(&["c-builder", "build", "--release"]);
+        let args = Cli::parse_from(["c-builder", "build", "--release"]);
         assert!(matches!(args.command, Commands::Build(_)));
     }
 
+    #[test]
+    fn test_build_profile() {
+        let args = Cli::parse_from(["c-builder", "build", "--profile", "asan"]);
+        match args.command {
+            Commands::Build(build) => assert_eq!(build.profile, Some("asan".to_string())),
+            _ => unreachable!(),
+        }
+
+        let args = Cli::try_parse_from(["c-builder", "build", "--profile", "asan", "--release"]);
+        assert!(args.is_err());
+    }
+
     #[test]
     fn test_run() {
-        let args = Cli::parse_from(&["c-builder", "run"]);
+        let args = Cli::parse_from(["c-builder", "run"]);
         assert!(matches!(args.command, Commands::Run(_)));
     }
 
     #[test]
     fn test_run_release() {
-        let args = Cli::parse_from(&["c-builder", "run", "--release"]);
+        let args = Cli::parse_from(["c-builder", "run", "--release"]);
         assert!(matches!(args.command, Commands::Run(_)));
     }
 
+    #[test]
+    fn test_run_bin() {
+        let args = Cli::parse_from(["c-builder", "run", "--bin", "server"]);
+        match args.command {
+            Commands::Run(run) => assert_eq!(run.bin, Some("server".to_string())),
+            _ => unreachable!(),
+        }
+
+        let args = Cli::try_parse_from(["c-builder", "run", "--bin", "server", "--benchmark"]);
+        assert!(args.is_err());
+    }
+
     #[test]
     fn test_memory_run() {
-        let args = Cli::parse_from(&["c-builder", "memory-run"]);
+        let args = Cli::parse_from(["c-builder", "memory-run"]);
         assert!(matches!(args.command, Commands::MemoryRun(_)));
     }
 
+    #[test]
+    fn test_memory_test_profile() {
+        let args = Cli::parse_from(["c-builder", "memory-test", "-p", "minsize"]);
+        match args.command {
+            Commands::MemoryTest(test) => assert_eq!(test.profile, Some("minsize".to_string())),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn test_memory_run_release() {
-        let args = Cli::parse_from(&["c-builder", "memory-run", "--release"]);
+        let args = Cli::parse_from(["c-builder", "memory-run", "--release"]);
         assert!(matches!(args.command, Commands::MemoryRun(_)));
     }
 
     #[test]
     fn test_init() {
-        let args = Cli::parse_from(&["c-builder", "init"]);
+        let args = Cli::parse_from(["c-builder", "init"]);
         assert!(matches!(args.command, Commands::Init(_)));
 
         let path = String::from("test");
-        let args = Cli::parse_from(&["c-builder", "init", &path]);
+        let args = Cli::parse_from(["c-builder", "init", &path]);
         match args.command {
             Commands::Init(init) => assert_eq!(init.path, path),
-            _ => assert!(false),
+            _ => unreachable!(),
         }
     }
 
     #[test]
     fn test_clean() {
-        let args = Cli::parse_from(&["c-builder", "clean"]);
+        let args = Cli::parse_from(["c-builder", "clean"]);
         assert!(matches!(args.command, Commands::Clean));
     }
+
+    #[test]
+    fn test_compdb() {
+        let args = Cli::parse_from(["c-builder", "compdb"]);
+        assert!(matches!(args.command, Commands::Compdb(_)));
+
+        let args = Cli::parse_from(["c-builder", "compdb", "--release"]);
+        match args.command {
+            Commands::Compdb(compdb) => assert!(compdb.release),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_tree() {
+        let args = Cli::parse_from(["c-builder", "tree"]);
+        match args.command {
+            Commands::Tree(tree) => {
+                assert_eq!(tree.format, TreeFormat::Text);
+                assert!(tree.invert.is_none());
+            }
+            _ => unreachable!(),
+        }
+
+        let args = Cli::parse_from(["c-builder", "tree", "--format", "dot", "--invert", "a.h"]);
+        match args.command {
+            Commands::Tree(tree) => {
+                assert_eq!(tree.format, TreeFormat::Dot);
+                assert_eq!(tree.invert, Some("a.h".to_string()));
+            }
+            _ => unreachable!(),
+        }
+
+        assert!(Cli::try_parse_from(["c-builder", "tree", "--format", "svg"]).is_err());
+    }
+
+    #[test]
+    fn test_test_report() {
+        let args = Cli::parse_from(["c-builder", "test", "--format", "junit", "-o", "report.xml"]);
+        match args.command {
+            Commands::Test(test) => {
+                assert_eq!(test.format, Some(ReportFormat::Junit));
+                assert_eq!(test.output, Some("report.xml".to_string()));
+                assert_eq!(test.jobs, 1);
+                assert_eq!(test.filter, None);
+            }
+            _ => unreachable!(),
+        }
+
+        assert!(Cli::try_parse_from(["c-builder", "test", "--format", "tap"]).is_err());
+        assert!(Cli::try_parse_from(["c-builder", "test", "--jobs", "many"]).is_err());
+        assert!(Cli::try_parse_from(["c-builder", "test", "-o", "report.xml"]).is_err());
+    }
 }
@@ -1,15 +1,226 @@
-pub fn output(command: &str) -> std::io::Result<std::process::ExitStatus> {
-    std::process::Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .stdin(std::process::Stdio::inherit())
-        .stdout(std::process::Stdio::inherit())
-        .status()
-}
-
-pub fn spawn(command: &str) -> std::io::Result<std::process::Child> {
-    std::process::Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .spawn()
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus, Output, Stdio};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    cwd: Option<PathBuf>,
+    process_group: bool,
+}
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Command {
+        Command {
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            env: Vec::new(),
+            cwd: None,
+            process_group: false,
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Command {
+        self.env
+            .push((key.as_ref().to_os_string(), value.as_ref().to_os_string()));
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Command {
+        self.cwd = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Starts the program in a new process group, so it can be killed with its children.
+    pub fn new_process_group(&mut self) -> &mut Command {
+        self.process_group = true;
+        self
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.program.is_empty() {
+            return Err("Failed to run command: no program given".to_string());
+        }
+        if self.program.as_encoded_bytes().contains(&0) {
+            return Err(format!(
+                "Failed to run `{}`: the program name contains a NUL byte",
+                self.program.to_string_lossy()
+            ));
+        }
+        for (index, arg) in self.args.iter().enumerate() {
+            if arg.as_encoded_bytes().contains(&0) {
+                return Err(format!(
+                    "Failed to run `{}`: argument {} (`{}`) contains a NUL byte",
+                    self.program.to_string_lossy(),
+                    index + 1,
+                    arg.to_string_lossy()
+                ));
+            }
+        }
+        for (key, _) in &self.env {
+            if key.is_empty() || key.as_encoded_bytes().contains(&b'=') {
+                return Err(format!(
+                    "Failed to run `{}`: invalid environment variable name `{}`",
+                    self.program.to_string_lossy(),
+                    key.to_string_lossy()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn to_process(&self) -> Result<std::process::Command, String> {
+        self.validate()?;
+        let mut process = std::process::Command::new(&self.program);
+        process.args(&self.args);
+        for (key, value) in &self.env {
+            process.env(key, value);
+        }
+        if let Some(cwd) = &self.cwd {
+            process.current_dir(cwd);
+        }
+        if self.process_group {
+            process.process_group(0);
+        }
+        Ok(process)
+    }
+
+    fn error(&self, e: std::io::Error) -> String {
+        match &self.cwd {
+            Some(cwd) => format!(
+                "Failed to run `{}` in `{}`: {}",
+                self.program.to_string_lossy(),
+                cwd.to_string_lossy(),
+                e
+            ),
+            None => format!("Failed to run `{}`: {}", self.program.to_string_lossy(), e),
+        }
+    }
+
+    /// Runs the command to completion with the standard streams inherited.
+    pub fn status(&self) -> Result<ExitStatus, String> {
+        self.to_process()?
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .status()
+            .map_err(|e| self.error(e))
+    }
+
+    pub fn spawn(&self) -> Result<Child, String> {
+        self.to_process()?.spawn().map_err(|e| self.error(e))
+    }
+
+    /// Starts the command with stdout and stderr piped, for reading while it runs.
+    pub fn spawn_piped(&self) -> Result<Child, String> {
+        self.to_process()?
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| self.error(e))
+    }
+
+    /// Runs the command to completion, capturing stdout and stderr.
+    pub fn output(&self) -> Result<Output, String> {
+        self.to_process()?.output().map_err(|e| self.error(e))
+    }
+}
+
+fn quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{}={} ", key.to_string_lossy(), quote(value))?;
+        }
+        write!(f, "{}", quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut command = Command::new("gcc");
+        command.args(["-O0", "my project/main.c", "-o", "it's"]);
+        assert_eq!(
+            command.to_string(),
+            "gcc -O0 'my project/main.c' -o 'it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn test_no_shell() {
+        let output = Command::new("echo").arg("$HOME; ls").output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "$HOME; ls\n");
+    }
+
+    #[test]
+    fn test_env_and_current_dir() {
+        let output = Command::new("sh")
+            .args(["-c", "echo $GREETING; pwd"])
+            .env("GREETING", "hello")
+            .current_dir("/")
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n/\n");
+    }
+
+    #[test]
+    fn test_nul_argument() {
+        let error = Command::new("echo")
+            .args(["fine", "bad\0arg"])
+            .output()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "Failed to run `echo`: argument 2 (`bad\0arg`) contains a NUL byte"
+        );
+    }
+
+    #[test]
+    fn test_missing_program() {
+        let error = Command::new("c-builder-does-not-exist")
+            .output()
+            .unwrap_err();
+        assert!(error.starts_with("Failed to run `c-builder-does-not-exist`: "));
+    }
 }
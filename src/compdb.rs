@@ -0,0 +1,225 @@
+use crate::build::{
+    get_binaries, get_build_options, get_cflags, get_includes, get_object_path, get_scan_options,
+    Config,
+};
+use crate::cli::{Build, Compdb};
+use crate::includes::{Include, IncludeType};
+use crate::library::{get_library_entry, get_library_includes};
+use crate::test::build::{get_test_includes, TEST_INCLUDE_FLAG};
+use crate::test::test_framework;
+use crate::toolchain::get_cc;
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+const COMPDB_FILE: &str = "compile_commands.json";
+
+#[derive(Debug, Serialize, PartialEq)]
+struct CompileCommand {
+    directory: String,
+    file: String,
+    arguments: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+fn get_compile_command(
+    directory: &Path,
+    source: &Path,
+    output: Option<PathBuf>,
+    config: &Config,
+) -> CompileCommand {
+    let source = directory.join(source);
+    let mut arguments = vec![get_cc(&config.toolchain).to_string()];
+    arguments.extend(get_cflags(config, false));
+    arguments.push("-c".to_string());
+    arguments.push(source.to_string_lossy().to_string());
+    if let Some(output) = &output {
+        arguments.push("-o".to_string());
+        arguments.push(output.to_string_lossy().to_string());
+    }
+
+    CompileCommand {
+        directory: directory.to_string_lossy().to_string(),
+        file: source.to_string_lossy().to_string(),
+        arguments,
+        output: output.map(|output| output.to_string_lossy().to_string()),
+    }
+}
+
+fn add_includes(
+    commands: &mut Vec<CompileCommand>,
+    directory: &Path,
+    includes: &Vec<Include>,
+    config: &Config,
+) {
+    for include in includes {
+        if let IncludeType::Local(path) = &include.kind {
+            commands.push(get_compile_command(
+                directory,
+                &path.with_extension("c"),
+                Some(get_object_path(include, config, false)),
+                config,
+            ));
+        }
+    }
+}
+
+fn get_compile_commands(profile: &str) -> Result<Vec<CompileCommand>, String> {
+    let directory = match std::env::current_dir().and_then(|dir| dir.canonicalize()) {
+        Ok(directory) => directory,
+        Err(e) => return Err(format!("Failed to get current directory: {}", e)),
+    };
+    let mut commands = Vec::new();
+
+    let config = get_build_options(&Build {
+        release: false,
+        benchmark: false,
+        profile: Some(profile.to_string()),
+        asm: false,
+        bin: None,
+    })?;
+    for binary in get_binaries(&config) {
+        let entry = PathBuf::from(&binary.path);
+        commands.push(get_compile_command(&directory, &entry, None, &config));
+        add_includes(
+            &mut commands,
+            &directory,
+            &get_includes(&config, &entry, &get_scan_options(&config))?,
+            &config,
+        );
+    }
+
+    if let Some(library) = &config.lib {
+        add_includes(
+            &mut commands,
+            &directory,
+            &get_library_includes(&config, &get_library_entry(&config, library))?,
+            &config,
+        );
+    }
+
+    let benchmark = PathBuf::from(&config.package.benchmark).join("main.c");
+    if benchmark.exists() {
+        let config = get_build_options(&Build {
+            release: false,
+            benchmark: true,
+            profile: None,
+            asm: false,
+            bin: None,
+        })?;
+        commands.push(get_compile_command(&directory, &benchmark, None, &config));
+        add_includes(
+            &mut commands,
+            &directory,
+            &get_includes(&config, &benchmark, &get_scan_options(&config))?,
+            &config,
+        );
+    }
+
+    if Path::new("tests").is_dir() {
+        for file in test_framework::get_test_files()? {
+            let mut command = get_compile_command(&directory, &file, None, &config);
+            command.arguments.insert(1, TEST_INCLUDE_FLAG.to_string());
+            commands.push(command);
+        }
+        add_includes(
+            &mut commands,
+            &directory,
+            &get_test_includes(&config)?,
+            &config,
+        );
+    }
+
+    let mut seen = Vec::new();
+    commands.retain(|command| {
+        if seen.contains(&command.file) {
+            false
+        } else {
+            seen.push(command.file.clone());
+            true
+        }
+    });
+
+    Ok(commands)
+}
+
+pub fn write_compdb(profile: &str) -> Result<(), String> {
+    let commands = get_compile_commands(profile)?;
+    let json = match serde_json::to_string_pretty(&commands) {
+        Ok(json) => json,
+        Err(e) => return Err(format!("Failed to serialize compilation database: {}", e)),
+    };
+
+    match std::fs::write(COMPDB_FILE, json) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to write {}: {}", COMPDB_FILE, e)),
+    }
+}
+
+pub fn compdb(args: &Compdb) -> Result<String, String> {
+    let profile = match &args.profile {
+        Some(profile) => profile.as_str(),
+        None if args.release => "release",
+        None => "debug",
+    };
+    write_compdb(profile)?;
+
+    Ok(format!("Wrote {}", COMPDB_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::{resolve_profiles, BuildArgs, Memory};
+
+    #[test]
+    fn test_get_compile_command() {
+        let mut config = Config {
+            profile: "debug".to_string(),
+            benchmark: Some(false),
+            package: Default::default(),
+            debug: BuildArgs {
+                debug: true,
+                optimization: 0,
+                std: "c11".to_string(),
+                asm: Some(false),
+                ..Default::default()
+            },
+            release: Default::default(),
+            memory: Memory {
+                leak_check: "".to_string(),
+                show_leak_kinds: "".to_string(),
+                track_origins: false,
+            },
+            ..Default::default()
+        };
+        resolve_profiles(&mut config).unwrap();
+
+        let command = get_compile_command(
+            Path::new("/project"),
+            Path::new("src/test.c"),
+            Some(PathBuf::from("c_target/debug/obj/src/test.o")),
+            &config,
+        );
+        assert_eq!(
+            command,
+            CompileCommand {
+                directory: "/project".to_string(),
+                file: "/project/src/test.c".to_string(),
+                arguments: vec![
+                    "gcc".to_string(),
+                    "-O0".to_string(),
+                    "-g".to_string(),
+                    "-std=c11".to_string(),
+                    "-c".to_string(),
+                    "/project/src/test.c".to_string(),
+                    "-o".to_string(),
+                    "c_target/debug/obj/src/test.o".to_string(),
+                ],
+                output: Some("c_target/debug/obj/src/test.o".to_string()),
+            }
+        );
+    }
+}
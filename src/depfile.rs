@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Parses a make-style dependency file as written by `gcc -MMD -MF`.
+pub fn parse(contents: &str) -> Vec<PathBuf> {
+    let mut dependencies = Vec::new();
+    let contents = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+
+    for line in contents.lines() {
+        let rule = match find_separator(line) {
+            Some(index) => &line[index + 1..],
+            None => continue,
+        };
+
+        let mut current = String::new();
+        let mut chars = rule.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some(' ') | Some('#')) => {
+                    current.push(chars.next().unwrap());
+                }
+                '$' if chars.peek() == Some(&'$') => {
+                    current.push(chars.next().unwrap());
+                }
+                c if c.is_whitespace() => {
+                    if !current.is_empty() {
+                        dependencies.push(PathBuf::from(&current));
+                        current.clear();
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            dependencies.push(PathBuf::from(current));
+        }
+    }
+
+    dependencies
+}
+
+fn find_separator(line: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            ':' if !escaped => {
+                let rest = &line[index + 1..];
+                if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                    return Some(index);
+                }
+                escaped = false;
+            }
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// A missing dependency file is treated as out of date.
+pub fn is_outdated(path: &Path, built: SystemTime) -> Result<bool, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => match e.kind() {
+            std::io::ErrorKind::NotFound => return Ok(true),
+            _ => return Err(format!("Failed to read dependency file: {}", e)),
+        },
+    };
+
+    for dependency in parse(&contents) {
+        match fs::metadata(&dependency).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => {
+                if modified > built {
+                    return Ok(true);
+                }
+            }
+            Err(_) => return Ok(true),
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "c_target/debug/obj/1.o: /src/test.c /src/test.h \\\n /src/other.h\n";
+        assert_eq!(
+            parse(contents),
+            vec![
+                PathBuf::from("/src/test.c"),
+                PathBuf::from("/src/test.h"),
+                PathBuf::from("/src/other.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        let contents = "obj/1.o: /my\\ project/test.c /cost$$/a.h\n";
+        assert_eq!(
+            parse(contents),
+            vec![
+                PathBuf::from("/my project/test.c"),
+                PathBuf::from("/cost$/a.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert!(parse("").is_empty());
+        assert!(parse("obj/1.o:\n").is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::command::Command;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return PathBuf::from(program).canonicalize().ok();
+    }
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Identifies the compiler by its resolved path and the first line of `--version`.
+pub fn get_compiler_id(compiler: &str) -> Result<String, String> {
+    let path = match find_in_path(compiler) {
+        Some(path) => path,
+        None => return Err(format!("Failed to find compiler `{}`", compiler)),
+    };
+
+    let output = Command::new(compiler).arg("--version").output()?;
+    let version = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    Ok(format!("{} {}", path.to_string_lossy(), version))
+}
+
+pub fn get_fingerprint(compiler_id: &str, cflags: &[String], source: &Path) -> String {
+    format!(
+        "compiler: {}\ncflags: {}\nsource: {}\n",
+        compiler_id,
+        cflags.join(" "),
+        source.to_string_lossy()
+    )
+}
+
+pub fn is_current(path: &Path, fingerprint: &str) -> bool {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents == fingerprint,
+        Err(_) => false,
+    }
+}
+
+pub fn write(path: &Path, fingerprint: &str) -> Result<(), String> {
+    match fs::write(path, fingerprint) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to write fingerprint: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_fingerprint() {
+        let cflags = ["-O0", "-g", "-std=c11"].map(String::from);
+        let fingerprint = get_fingerprint("/usr/bin/gcc gcc 14.2.0", &cflags, Path::new("src/a.c"));
+        assert_eq!(
+            fingerprint,
+            "compiler: /usr/bin/gcc gcc 14.2.0\ncflags: -O0 -g -std=c11\nsource: src/a.c\n"
+        );
+    }
+
+    #[test]
+    fn test_is_current_missing() {
+        assert!(!is_current(Path::new("does/not/exist.fingerprint"), ""));
+    }
+}
@@ -1,5 +1,6 @@
-use std::fs::File;
-use std::io::BufRead;
+use crate::preprocessor::{scan, Defines, IncludeKind};
+
+use std::fs;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -13,126 +14,173 @@ pub struct Include {
     pub kind: IncludeType,
 }
 
-fn has_c_file(path: &Path, name: &str) -> bool {
-    let file = open_file(&path.join(name).with_extension("c"));
-    file.is_ok()
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions {
+    pub defines: Defines,
+    pub include_dirs: Vec<PathBuf>,
+    pub src: PathBuf,
 }
 
-fn open_file(path: &PathBuf) -> Result<File, String> {
-    match File::open(path) {
-        Ok(file) => Ok(file),
-        Err(e) => Err(format!("Failed to open file: {}", e)),
+/// Looks up an included header the way the compiler does: quoted includes are first
+/// searched next to the including file, then every include directory in order.
+/// Returns the header and the include directory it was found in, if any.
+fn find_header(
+    path: &Path,
+    kind: &IncludeKind,
+    options: &ScanOptions,
+) -> Option<(PathBuf, Option<PathBuf>)> {
+    let name = match kind {
+        IncludeKind::Local(name) => {
+            if let Ok(header) = path.join(name).canonicalize() {
+                if header.is_file() {
+                    return Some((header, None));
+                }
+            }
+            name
+        }
+        IncludeKind::System(name) => name,
+    };
+    options.include_dirs.iter().find_map(|dir| {
+        let header = dir.join(name).canonicalize().ok()?;
+        if header.is_file() {
+            Some((header, Some(dir.clone())))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the `.c` source of a header. Headers in an include directory are matched with a
+/// source at the same relative path in `src`, or directly in it.
+fn find_source(
+    header: &Path,
+    name: &str,
+    include_dir: Option<&Path>,
+    options: &ScanOptions,
+) -> Option<PathBuf> {
+    let source = header.with_extension("c");
+    if source.is_file() {
+        return Some(source);
     }
+    include_dir?;
+    let name = Path::new(name);
+    [options.src.join(name), options.src.join(name.file_name()?)]
+        .into_iter()
+        .find_map(|path| path.with_extension("c").canonicalize().ok())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dependency {
+    System(String),
+    /// A project header, with its `.c` source if it has one.
+    Header {
+        path: PathBuf,
+        source: Option<PathBuf>,
+    },
 }
 
-fn get_include_from_line(line: &str) -> (String, String) {
-    let include = line.strip_prefix("#include").unwrap().trim().to_string();
-    let name = include[1..include.len() - 1].to_string();
+/// Scans a single file and resolves each of its active includes, in order.
+pub fn get_dependencies(file: &Path, options: &ScanOptions) -> Result<Vec<Dependency>, String> {
+    let contents = match fs::read(file) {
+        Ok(contents) => String::from_utf8_lossy(&contents).to_string(),
+        Err(e) => return Err(format!("Failed to open file: {}", e)),
+    };
+    let path = file.parent().unwrap_or(Path::new(""));
 
-    (include, name)
+    let mut dependencies = Vec::new();
+    for directive in scan(&contents, &file.to_string_lossy(), &options.defines)? {
+        let name = match &directive.kind {
+            IncludeKind::Local(name) | IncludeKind::System(name) => name.clone(),
+        };
+        // Like the compiler, quoted includes that are not found fall back to the system
+        // headers.
+        dependencies.push(match find_header(path, &directive.kind, options) {
+            Some((header, include_dir)) => Dependency::Header {
+                source: find_source(&header, &name, include_dir.as_deref(), options),
+                path: header,
+            },
+            None => Dependency::System(name),
+        });
+    }
+    Ok(dependencies)
 }
 
 pub fn get_includes_from_file(
     path: &Path,
     name: String,
     already_included: &mut Vec<String>,
+    options: &ScanOptions,
 ) -> Result<Vec<Include>, String> {
-    if already_included.contains(&name) {
+    let file = path.join(&name);
+    let key = file
+        .canonicalize()
+        .unwrap_or_else(|_| file.clone())
+        .to_string_lossy()
+        .to_string();
+    if already_included.contains(&key) {
         return Ok(Vec::new());
     }
-    already_included.push(name.clone());
+    already_included.push(key);
 
     let mut includes = Vec::new();
 
-    let file = open_file(&path.join(name))?;
-
-    let mut contents = std::io::BufReader::new(file);
-
-    let mut line: String = String::new();
-
-    while match contents.read_line(&mut line) {
-        Ok(0) => false,
-        Ok(_) => true,
-        Err(_) => false,
-    } {
-        if line.starts_with("#include") {
-            let (include, mut name) = get_include_from_line(&line);
-
-            if already_included.contains(&name.to_string()) {
-                line.clear();
+    for dependency in get_dependencies(&file, options)? {
+        let (header, source) = match dependency {
+            Dependency::Header { path, source } => (path, source),
+            Dependency::System(name) => {
+                let name = format!("<{}>", name);
+                if !already_included.contains(&name) {
+                    includes.push(Include {
+                        kind: IncludeType::System,
+                    });
+                    already_included.push(name);
+                }
                 continue;
             }
+        };
 
-            if include.starts_with("\"") {
-                let name_as_path = PathBuf::from(&name);
-
-                let relative_path = match if let Some(parent) = name_as_path.parent() {
-                    name = name_as_path
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string();
-                    path.join(parent)
-                } else {
-                    path.to_path_buf()
-                }
-                .canonicalize()
-                {
-                    Ok(path) => path,
-                    Err(_) => {
-                        return Err(format!(
-                            "Included header file `{}/{}` not found",
-                            path.to_str().unwrap(),
-                            name
-                        ));
-                    }
-                };
-
-                if !has_c_file(&relative_path, &name) {
-                    if !already_included.contains(&name.to_string()) {
-                        println!(
-                            "Note: Included header file `{}/{}`, has no corresponding source file",
-                            relative_path.to_str().unwrap(),
-                            name
-                        );
-                        already_included.push(name.to_string());
-                    }
-                    line.clear();
-                    continue;
-                }
+        if already_included.contains(&header.to_string_lossy().to_string()) {
+            continue;
+        }
 
+        match source {
+            Some(source) => {
                 includes.push(Include {
-                    kind: IncludeType::Local(relative_path.join(&name)),
+                    kind: IncludeType::Local(source.clone()),
                 });
-
                 includes.append(&mut get_includes_from_file(
-                    &relative_path,
-                    name.clone(),
+                    source.parent().unwrap(),
+                    source.file_name().unwrap().to_string_lossy().to_string(),
                     already_included,
+                    options,
                 )?);
-
-                includes.append(&mut get_includes_from_file(
-                    &relative_path,
-                    PathBuf::from(&name)
-                        .with_extension("c")
-                        .to_string_lossy()
-                        .to_string(),
-                    already_included,
-                )?);
-            } else {
-                includes.push(Include {
-                    kind: IncludeType::System,
-                });
-                already_included.push(name.to_string());
             }
+            None => eprintln!(
+                "Note: Included header file `{}`, has no corresponding source file",
+                header.to_string_lossy()
+            ),
         }
-        line.clear();
+
+        includes.append(&mut get_includes_from_file(
+            header.parent().unwrap(),
+            header.file_name().unwrap().to_string_lossy().to_string(),
+            already_included,
+            options,
+        )?);
     }
     Ok(includes)
 }
 
-pub fn get_includes(path: PathBuf) -> Result<Vec<Include>, String> {
-    let mut includes = get_includes_from_file(&path, "main.c".to_string(), &mut Vec::new())?;
+pub fn get_includes_from_entry(
+    entry: &Path,
+    options: &ScanOptions,
+) -> Result<Vec<Include>, String> {
+    let name = match entry.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return Err(format!("Invalid entry file `{}`", entry.to_string_lossy())),
+    };
+    let path = entry.parent().unwrap_or(Path::new(""));
+    let mut includes = get_includes_from_file(path, name, &mut Vec::new(), options)?;
     includes.sort();
     includes.dedup();
     Ok(includes)
@@ -143,28 +191,69 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_get_includes() {
-        let includes = get_includes(PathBuf::from("examples/tests/src")).unwrap();
+    fn test_get_includes_from_entry() {
+        let includes = get_includes_from_entry(
+            Path::new("examples/tests/src/main.c"),
+            &ScanOptions::default(),
+        )
+        .unwrap();
         assert_eq!(includes.len(), 3);
+        assert!(includes.contains(&Include {
+            kind: IncludeType::Local(
+                PathBuf::from("examples/tests/src/testing.c")
+                    .canonicalize()
+                    .unwrap()
+            )
+        }));
+        assert!(includes.contains(&Include {
+            kind: IncludeType::Local(
+                PathBuf::from("examples/tests/src/test.c")
+                    .canonicalize()
+                    .unwrap()
+            )
+        }));
+    }
+
+    #[test]
+    fn test_get_includes_from_entry_include_dirs() {
+        let root = std::env::temp_dir().join("c-builder-test-include-dirs");
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["include/proj", "src/net", "src/fs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let files = [
+            (
+                "src/main.c",
+                "#include <proj/foo.h>\n#include \"net/util.h\"\n#include \"fs/util.h\"\n",
+            ),
+            ("include/proj/foo.h", ""),
+            ("src/foo.c", "#include <proj/foo.h>\n"),
+            ("src/net/util.h", ""),
+            ("src/net/util.c", ""),
+            ("src/fs/util.h", ""),
+            ("src/fs/util.c", ""),
+        ];
+        for (name, contents) in files {
+            fs::write(root.join(name), contents).unwrap();
+        }
+
+        let options = ScanOptions {
+            include_dirs: vec![root.join("include")],
+            src: root.join("src"),
+            ..Default::default()
+        };
+        let includes = get_includes_from_entry(&root.join("src/main.c"), &options).unwrap();
+        let root = root.canonicalize().unwrap();
         assert_eq!(
-            includes.contains(&Include {
-                kind: IncludeType::Local(
-                    PathBuf::from("examples/tests/src/testing.c")
-                        .canonicalize()
-                        .unwrap()
-                )
-            }),
-            true
-        );
-        assert_eq!(
-            includes.contains(&Include {
-                kind: IncludeType::Local(
-                    PathBuf::from("examples/tests/src/test.h")
-                        .canonicalize()
-                        .unwrap()
-                )
-            }),
-            true
+            includes,
+            [
+                root.join("src/foo.c"),
+                root.join("src/fs/util.c"),
+                root.join("src/net/util.c"),
+            ]
+            .map(|path| Include {
+                kind: IncludeType::Local(path)
+            })
         );
     }
 }
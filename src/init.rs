@@ -1,4 +1,5 @@
 use crate::cli::Init;
+use crate::command::Command;
 
 use crate::test::test_framework::create_test_framework;
 
@@ -54,9 +55,7 @@ fn create_main_file(path: &str) -> Result<(), String> {
 }
 
 fn create_git_repo(path: &str) -> Result<(), String> {
-    let command = format!("cd {} && git init", path);
-
-    match crate::command::output(&command) {
+    match Command::new("git").arg("init").current_dir(path).status() {
         Ok(_) => Ok(()),
         Err(e) => Err(format!("Failed to create git repo: {}", e)),
     }
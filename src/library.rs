@@ -0,0 +1,228 @@
+use crate::build::{
+    build_object_files, get_includes, get_link_args, get_object_path, get_scan_options, get_target,
+    Config,
+};
+use crate::command::Command;
+use crate::includes::{Include, IncludeType};
+use crate::toolchain::{get_ar, get_ld};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LibraryKind {
+    Static,
+    Shared,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct Library {
+    pub name: Option<String>,
+    pub path: Option<String>,
+    pub kind: Option<Vec<LibraryKind>>,
+}
+
+fn get_library_name(config: &Config, library: &Library) -> String {
+    library
+        .name
+        .clone()
+        .unwrap_or_else(|| config.package.name.clone())
+}
+
+pub fn get_library_entry(config: &Config, library: &Library) -> PathBuf {
+    match &library.path {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(&config.package.src).join("lib.c"),
+    }
+}
+
+fn get_library_kinds(library: &Library) -> Vec<LibraryKind> {
+    library
+        .kind
+        .clone()
+        .unwrap_or(vec![LibraryKind::Static, LibraryKind::Shared])
+}
+
+/// Returns the file names of the shared library in the order they link to each other:
+/// the real file, the soname and the development symlink.
+fn get_shared_names(name: &str, version: &str) -> (String, Option<String>, String) {
+    let link = format!("lib{}.so", name);
+    if version.is_empty() {
+        return (link.clone(), None, link);
+    }
+
+    let major = version.split('.').next().unwrap_or(version);
+    (
+        format!("{}.{}", link, version),
+        Some(format!("{}.{}", link, major)),
+        link,
+    )
+}
+
+pub fn get_library_includes(config: &Config, entry: &Path) -> Result<Vec<Include>, String> {
+    let mut includes = get_includes(config, entry, &get_scan_options(config))?;
+    let entry = match entry.canonicalize() {
+        Ok(entry) => entry,
+        Err(e) => {
+            return Err(format!(
+                "Failed to find library entry `{}`: {}",
+                entry.to_string_lossy(),
+                e
+            ))
+        }
+    };
+    includes.push(Include {
+        kind: IncludeType::Local(entry),
+    });
+    includes.sort();
+    includes.dedup();
+    Ok(includes)
+}
+
+fn get_objects(includes: &[Include], config: &Config, pic: bool) -> Vec<PathBuf> {
+    includes
+        .iter()
+        .filter(|include| matches!(include.kind, IncludeType::Local(_)))
+        .map(|include| get_object_path(include, config, pic))
+        .collect()
+}
+
+fn symlink(original: &str, link: &Path) -> Result<(), String> {
+    if fs::symlink_metadata(link).is_ok() {
+        if let Err(e) = fs::remove_file(link) {
+            return Err(format!("Failed to remove old symlink: {}", e));
+        }
+    }
+    match std::os::unix::fs::symlink(original, link) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to create symlink: {}", e)),
+    }
+}
+
+fn build_static(name: &str, includes: &Vec<Include>, config: &Config) -> Result<(), String> {
+    build_object_files(includes, config, false)?;
+
+    let archive = PathBuf::from(get_target(config)).join(format!("lib{}.a", name));
+    if archive.exists() {
+        if let Err(e) = fs::remove_file(&archive) {
+            return Err(format!("Failed to remove old archive: {}", e));
+        }
+    }
+
+    let mut command = Command::new(get_ar(&config.toolchain));
+    command
+        .arg("rcs")
+        .arg(&archive)
+        .args(get_objects(includes, config, false));
+
+    println!("Running command: {}", command);
+    if !command.status()?.success() {
+        return Err(format!(
+            "Failed to build static library `{}`",
+            archive.to_string_lossy()
+        ));
+    }
+    Ok(())
+}
+
+fn build_shared(name: &str, includes: &Vec<Include>, config: &Config) -> Result<(), String> {
+    build_object_files(includes, config, true)?;
+
+    let target = PathBuf::from(get_target(config));
+    let (real, soname, link) = get_shared_names(name, &config.package.version);
+
+    let mut command = Command::new(get_ld(&config.toolchain));
+    command.arg("-shared");
+    if let Some(soname) = &soname {
+        command.arg(format!("-Wl,-soname,{}", soname));
+    }
+    command
+        .arg("-o")
+        .arg(target.join(&real))
+        .args(get_objects(includes, config, true))
+        .args(get_link_args(config));
+
+    println!("Running command: {}", command);
+    if !command.status()?.success() {
+        return Err(format!("Failed to build shared library `{}`", real));
+    }
+
+    if let Some(soname) = &soname {
+        symlink(&real, &target.join(soname))?;
+        symlink(soname, &target.join(&link))?;
+    }
+    Ok(())
+}
+
+pub fn build_library(config: &Config) -> Result<(), String> {
+    let library = match &config.lib {
+        Some(library) => library,
+        None => return Ok(()),
+    };
+
+    let name = get_library_name(config, library);
+    let includes = get_library_includes(config, &get_library_entry(config, library))?;
+
+    println!("Building library {}", name);
+    for kind in get_library_kinds(library) {
+        match kind {
+            LibraryKind::Static => build_static(&name, &includes, config)?,
+            LibraryKind::Shared => build_shared(&name, &includes, config)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_shared_names() {
+        assert_eq!(
+            get_shared_names("foo", "1.2.3"),
+            (
+                "libfoo.so.1.2.3".to_string(),
+                Some("libfoo.so.1".to_string()),
+                "libfoo.so".to_string()
+            )
+        );
+        assert_eq!(
+            get_shared_names("foo", ""),
+            ("libfoo.so".to_string(), None, "libfoo.so".to_string())
+        );
+    }
+
+    #[test]
+    fn test_get_library_kinds() {
+        let library = Library::default();
+        assert_eq!(
+            get_library_kinds(&library),
+            [LibraryKind::Static, LibraryKind::Shared]
+        );
+
+        let library = Library {
+            kind: Some(vec![LibraryKind::Shared]),
+            ..Default::default()
+        };
+        assert_eq!(get_library_kinds(&library), [LibraryKind::Shared]);
+    }
+
+    #[test]
+    fn test_get_library_entry() {
+        let config = Config {
+            package: crate::build::Package {
+                src: "source".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            get_library_entry(&config, &Library::default()),
+            PathBuf::from("source/lib.c")
+        );
+    }
+}
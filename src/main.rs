@@ -4,10 +4,19 @@ mod build;
 mod clean;
 mod cli;
 mod command;
+mod compdb;
+mod depfile;
+mod fingerprint;
 mod includes;
 mod init;
+mod library;
+mod pkg_config;
+mod preprocessor;
 mod run;
+mod sources;
 mod test;
+mod toolchain;
+mod tree;
 
 fn main() {
     let command = cli::Cli::parse();
@@ -20,6 +29,8 @@ fn main() {
         cli::Commands::MemoryTest(test) => test::run::memory_run(&test),
         cli::Commands::Init(init) => init::init(&init),
         cli::Commands::Clean => clean::clean(),
+        cli::Commands::Compdb(compdb) => compdb::compdb(&compdb),
+        cli::Commands::Tree(tree) => tree::tree(&tree),
     } {
         Ok(v) => {
             if !v.is_empty() {
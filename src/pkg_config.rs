@@ -0,0 +1,93 @@
+use crate::command::Command;
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// The configuration is loaded several times by a command, pkg-config only runs once for
+/// every set of packages.
+static RESOLVED: Mutex<BTreeMap<Vec<String>, PkgConfig>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PkgConfig {
+    pub cflags: Vec<String>,
+    pub libs: Vec<String>,
+}
+
+/// Splits pkg-config output into arguments, undoing the backslash escapes it uses for spaces.
+fn split_flags(output: &str) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut current = String::new();
+    let mut chars = output.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    flags.push(current.clone());
+                    current.clear();
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        flags.push(current);
+    }
+    flags
+}
+
+fn query(flag: &str, packages: &[String]) -> Result<Vec<String>, String> {
+    let output = Command::new("pkg-config")
+        .arg(flag)
+        .args(packages)
+        .output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "pkg-config {} {} failed: {}",
+            flag,
+            packages.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(split_flags(&String::from_utf8_lossy(&output.stdout)))
+}
+
+pub fn resolve(packages: &[String]) -> Result<PkgConfig, String> {
+    if packages.is_empty() {
+        return Ok(PkgConfig::default());
+    }
+
+    let mut resolved = RESOLVED.lock().unwrap();
+    if let Some(pkg_config) = resolved.get(packages) {
+        return Ok(pkg_config.clone());
+    }
+    let pkg_config = PkgConfig {
+        cflags: query("--cflags", packages)?,
+        libs: query("--libs", packages)?,
+    };
+    resolved.insert(packages.to_vec(), pkg_config.clone());
+    Ok(pkg_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_flags() {
+        assert_eq!(
+            split_flags("-I/usr/include/my\\ lib  -DFOO=1\n"),
+            ["-I/usr/include/my lib", "-DFOO=1"]
+        );
+        assert!(split_flags(" \n").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_empty() {
+        assert_eq!(resolve(&[]).unwrap(), PkgConfig::default());
+    }
+}
@@ -0,0 +1,960 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+pub type Defines = BTreeMap<String, Option<String>>;
+
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncludeKind {
+    Local(String),
+    System(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeDirective {
+    pub line: usize,
+    pub kind: IncludeKind,
+}
+
+/// Macros the compiler defines on its own that headers commonly check before including.
+pub fn get_predefined() -> Defines {
+    let mut defines = Defines::new();
+    defines.insert("__STDC__".to_string(), Some("1".to_string()));
+    defines.insert("__STDC_HOSTED__".to_string(), Some("1".to_string()));
+    if cfg!(unix) {
+        defines.insert("__unix__".to_string(), Some("1".to_string()));
+    }
+    if cfg!(target_os = "linux") {
+        defines.insert("__linux__".to_string(), Some("1".to_string()));
+    }
+    if cfg!(target_os = "macos") {
+        defines.insert("__APPLE__".to_string(), Some("1".to_string()));
+    }
+    defines
+}
+
+/// Whether the compiler sees a line. Lines are `Unknown` when that depends on macros that
+/// are not known, like the ones defined by included headers or built into the compiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Active,
+    Inactive,
+    Unknown,
+}
+
+/// The state of a conditional branch. `active` is what the branch evaluates to with unknown
+/// macros taken as undefined, `certain` whether it evaluates to that whatever they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    active: bool,
+    certain: bool,
+}
+
+impl State {
+    const ACTIVE: State = State {
+        active: true,
+        certain: true,
+    };
+    const INACTIVE: State = State {
+        active: false,
+        certain: true,
+    };
+
+    fn not(self) -> State {
+        State {
+            active: !self.active,
+            certain: self.certain,
+        }
+    }
+
+    fn and(self, other: State) -> State {
+        State {
+            active: self.active && other.active,
+            certain: (self.certain && other.certain)
+                || self == State::INACTIVE
+                || other == State::INACTIVE,
+        }
+    }
+
+    fn or(self, other: State) -> State {
+        self.not().and(other.not()).not()
+    }
+
+    fn activity(self) -> Activity {
+        match (self.active, self.certain) {
+            (_, false) => Activity::Unknown,
+            (true, true) => Activity::Active,
+            (false, true) => Activity::Inactive,
+        }
+    }
+}
+
+struct Conditional {
+    line: usize,
+    parent: State,
+    state: State,
+    /// Whether an earlier branch was taken.
+    taken: State,
+    seen_else: bool,
+}
+
+fn get_condition(name: &str, args: &str, defines: &Defines) -> Result<State, String> {
+    if name == "if" || name == "elif" {
+        let (value, certain) = evaluate(args, defines)?;
+        return Ok(State {
+            active: value != 0,
+            certain,
+        });
+    }
+    match get_identifier(args) {
+        Some(identifier) => {
+            let defined = defines.contains_key(identifier);
+            Ok(State {
+                active: defined == (name == "ifdef" || name == "elifdef"),
+                certain: defined,
+            })
+        }
+        None => Err(format!("#{} expects a macro name", name)),
+    }
+}
+
+/// Enters the next branch of a conditional with the given directive.
+fn enter_branch(
+    conditional: &mut Conditional,
+    name: &str,
+    args: &str,
+    defines: &Defines,
+) -> Result<(), String> {
+    let possible = conditional.parent.and(conditional.taken.not());
+    let condition = if possible == State::INACTIVE {
+        State::INACTIVE
+    } else {
+        match get_condition(name, args, defines) {
+            Ok(condition) => condition,
+            // Like the compiler, only branches that may be active must be well formed.
+            Err(_) if !possible.active => State {
+                active: false,
+                certain: false,
+            },
+            Err(e) => return Err(e),
+        }
+    };
+    conditional.state = possible.and(condition);
+    conditional.taken = conditional.taken.or(condition);
+    Ok(())
+}
+
+/// Joins lines ending in a backslash, keeping the number of the first physical line.
+fn splice_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+    for (index, line) in source.lines().enumerate() {
+        let (text, continues) = match line.strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (line, false),
+        };
+        if continued {
+            lines.last_mut().unwrap().1.push_str(text);
+        } else {
+            lines.push((index + 1, text.to_string()));
+        }
+        continued = continues;
+    }
+    lines
+}
+
+/// Removes comments from every line, replacing them with a space like the compiler does.
+/// Block comments may span several lines, so the state is carried between calls.
+fn strip_comments(line: &str, in_block: &mut bool) -> String {
+    let mut stripped = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        if *in_block {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                *in_block = false;
+                stripped.push(' ');
+            }
+            continue;
+        }
+
+        if let Some(q) = quote {
+            stripped.push(c);
+            if c == '\\' {
+                if let Some(next) = chars.next() {
+                    stripped.push(next);
+                }
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '/' if chars.peek() == Some(&'/') => break,
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                *in_block = true;
+            }
+            '"' | '\'' => {
+                quote = Some(c);
+                stripped.push(c);
+            }
+            c => stripped.push(c),
+        }
+    }
+
+    stripped
+}
+
+fn split_directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    Some((&rest[..end], rest[end..].trim()))
+}
+
+fn get_identifier(args: &str) -> Option<&str> {
+    let end = args
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(args.len());
+    let identifier = &args[..end];
+    if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        None
+    } else {
+        Some(identifier)
+    }
+}
+
+fn parse_include(args: &str, defines: &Defines) -> Result<IncludeKind, String> {
+    let mut args = args.to_string();
+    for _ in 0..MAX_EXPANSION_DEPTH {
+        if let Some(rest) = args.strip_prefix('"') {
+            return match rest.find('"') {
+                Some(end) if end > 0 => Ok(IncludeKind::Local(rest[..end].to_string())),
+                _ => Err(format!("malformed #include {}", args)),
+            };
+        }
+        if let Some(rest) = args.strip_prefix('<') {
+            return match rest.find('>') {
+                Some(end) if end > 0 => Ok(IncludeKind::System(rest[..end].to_string())),
+                _ => Err(format!("malformed #include {}", args)),
+            };
+        }
+
+        let identifier = match get_identifier(&args) {
+            Some(identifier) if identifier.len() == args.len() => identifier,
+            _ => return Err(format!("malformed #include {}", args)),
+        };
+        args = match defines.get(identifier) {
+            Some(Some(value)) => value.trim().to_string(),
+            _ => {
+                return Err(format!(
+                    "#include {} does not expand to a header name",
+                    identifier
+                ))
+            }
+        };
+    }
+    Err(format!("#include {} expands too deeply", args))
+}
+
+/// The parts of a file the compiler would see with the given macros.
+struct Preprocessed {
+    includes: Vec<IncludeDirective>,
+    /// Lines that are not directives, without comments.
+    lines: Vec<(usize, String, Activity)>,
+}
+
+fn preprocess(source: &str, file: &str, defines: &Defines) -> Result<Preprocessed, String> {
+    let mut defines = defines.clone();
+    let mut includes = Vec::new();
+    let mut lines = Vec::new();
+    let mut conditionals: Vec<Conditional> = Vec::new();
+    let mut in_block = false;
+    let mut pending: Option<(usize, String)> = None;
+
+    for (line, text) in splice_lines(source) {
+        let text = strip_comments(&text, &mut in_block);
+        // A block comment spanning lines joins them into one, so a directive can only start
+        // on the line where the comment began.
+        let (line, text) = match pending.take() {
+            Some((start, prefix)) => (start, prefix + &text),
+            None => (line, text),
+        };
+        if in_block {
+            pending = Some((line, text));
+            continue;
+        }
+        let state = conditionals
+            .last()
+            .map(|c| c.state)
+            .unwrap_or(State::ACTIVE);
+        let active = state.active;
+        let (name, args) = match split_directive(&text) {
+            Some(directive) => directive,
+            None => {
+                lines.push((line, text, state.activity()));
+                continue;
+            }
+        };
+        let error = |message: String| format!("{}:{}: {}", file, line, message);
+
+        match name {
+            "if" | "ifdef" | "ifndef" => {
+                conditionals.push(Conditional {
+                    line,
+                    parent: state,
+                    state: State::INACTIVE,
+                    taken: State::INACTIVE,
+                    seen_else: false,
+                });
+                enter_branch(conditionals.last_mut().unwrap(), name, args, &defines)
+                    .map_err(error)?;
+            }
+            "elif" | "elifdef" | "elifndef" => {
+                let conditional = match conditionals.last_mut() {
+                    Some(conditional) => conditional,
+                    None => return Err(error(format!("#{} without #if", name))),
+                };
+                if conditional.seen_else {
+                    return Err(error(format!("#{} after #else", name)));
+                }
+                enter_branch(conditional, name, args, &defines).map_err(error)?;
+            }
+            "else" => {
+                let conditional = match conditionals.last_mut() {
+                    Some(conditional) => conditional,
+                    None => return Err(error("#else without #if".to_string())),
+                };
+                if conditional.seen_else {
+                    return Err(error("#else after #else".to_string()));
+                }
+                conditional.state = conditional.parent.and(conditional.taken.not());
+                conditional.taken = State::ACTIVE;
+                conditional.seen_else = true;
+            }
+            "endif" if conditionals.pop().is_none() => {
+                return Err(error("#endif without #if".to_string()));
+            }
+            "endif" => (),
+            _ if !active => (),
+            "define" => match get_identifier(args) {
+                Some(identifier) => {
+                    let rest = &args[identifier.len()..];
+                    let value = if rest.starts_with('(') {
+                        None
+                    } else {
+                        Some(rest.trim().to_string())
+                    };
+                    defines.insert(identifier.to_string(), value);
+                }
+                None => return Err(error("#define expects a macro name".to_string())),
+            },
+            "undef" => match get_identifier(args) {
+                Some(identifier) => {
+                    defines.remove(identifier);
+                }
+                None => return Err(error("#undef expects a macro name".to_string())),
+            },
+            "include" | "include_next" => {
+                includes.push(IncludeDirective {
+                    line,
+                    kind: parse_include(args, &defines).map_err(error)?,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(conditional) = conditionals.last() {
+        return Err(format!(
+            "{}:{}: unterminated conditional directive",
+            file, conditional.line
+        ));
+    }
+
+    Ok(Preprocessed { includes, lines })
+}
+
+/// Finds the `#include` directives of a source file that are active with the given defines.
+/// `#define` and `#undef` directives in the file itself are taken into account as well.
+pub fn scan(source: &str, file: &str, defines: &Defines) -> Result<Vec<IncludeDirective>, String> {
+    Ok(preprocess(source, file, defines)?.includes)
+}
+
+/// Returns the numbered lines of the file that are not directives, with comments replaced
+/// by spaces, and whether the compiler sees them.
+pub fn get_lines(
+    source: &str,
+    file: &str,
+    defines: &Defines,
+) -> Result<Vec<(usize, String, Activity)>, String> {
+    Ok(preprocess(source, file, defines)?.lines)
+}
+
+/// Returns the lines of `file` that are not blank in the output of the compiler's
+/// preprocessor, following its line markers.
+pub fn get_output_lines(output: &str, file: &str) -> BTreeSet<usize> {
+    let mut lines = BTreeSet::new();
+    let mut current = (String::new(), 1);
+    for text in output.lines() {
+        if let Some(marker) = parse_line_marker(text) {
+            current = marker;
+            continue;
+        }
+        if current.0 == file && !text.trim().is_empty() {
+            lines.insert(current.1);
+        }
+        current.1 += 1;
+    }
+    lines
+}
+
+/// Parses a `# 12 "file"` or `#line 12 "file"` marker into the file and line it starts.
+fn parse_line_marker(text: &str) -> Option<(String, usize)> {
+    let rest = text.strip_prefix('#')?.trim_start();
+    let rest = rest.strip_prefix("line").unwrap_or(rest).trim_start();
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let line = rest[..end].parse().ok()?;
+    let name = rest[end..].trim_start().strip_prefix('"')?;
+    Some((name[..name.find('"')?].to_string(), line))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Identifier(String),
+    Operator(&'static str),
+    Other(char),
+}
+
+const OPERATORS: [&str; 25] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "!", "~", "-", "+", "*", "/", "%",
+    "<", ">", "&", "^", "|", "?", ":", ",",
+];
+
+fn parse_number(literal: &str) -> Result<i64, String> {
+    let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    parsed.map_err(|_| format!("invalid integer `{}` in expression", literal))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let identifier = get_identifier(rest).unwrap();
+            tokens.push(Token::Identifier(identifier.to_string()));
+            rest = &rest[identifier.len()..];
+        } else if c == '\'' {
+            let mut chars = rest[1..].chars();
+            let value = match (chars.next(), chars.next(), chars.next()) {
+                (Some('\\'), Some(escaped), Some('\'')) => match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    '0' => '\0',
+                    c => c,
+                },
+                (Some(c), Some('\''), _) => c,
+                _ => return Err("invalid character literal in expression".to_string()),
+            };
+            let length = if rest[1..].starts_with('\\') { 4 } else { 3 };
+            tokens.push(Token::Number(value as i64));
+            rest = &rest[length..];
+        } else {
+            match OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+            {
+                Some(operator) => {
+                    tokens.push(Token::Operator(operator));
+                    rest = &rest[operator.len()..];
+                }
+                None => {
+                    tokens.push(Token::Other(c));
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Replaces `defined` and macros with their values. Unknown identifiers become 0, and so
+/// do calls of unknown function-like macros such as `__has_include(...)`. Both clear
+/// `certain`, as the macros may be defined where the compiler evaluates the expression.
+fn expand(
+    tokens: Vec<Token>,
+    defines: &Defines,
+    depth: usize,
+    certain: &mut bool,
+) -> Result<Vec<Token>, String> {
+    if depth > MAX_EXPANSION_DEPTH {
+        return Err("macro expands too deeply".to_string());
+    }
+
+    let mut expanded = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let identifier = match token {
+            Token::Identifier(identifier) => identifier,
+            token => {
+                expanded.push(token);
+                continue;
+            }
+        };
+
+        if identifier == "defined" {
+            let parenthesized = tokens.peek() == Some(&Token::Operator("("));
+            if parenthesized {
+                tokens.next();
+            }
+            let name = match tokens.next() {
+                Some(Token::Identifier(name)) => name,
+                _ => return Err("`defined` expects a macro name".to_string()),
+            };
+            if parenthesized && tokens.next() != Some(Token::Operator(")")) {
+                return Err("missing `)` after `defined`".to_string());
+            }
+            let defined = defines.contains_key(&name);
+            *certain &= defined;
+            expanded.push(Token::Number(defined as i64));
+            continue;
+        }
+
+        match defines.get(&identifier) {
+            Some(Some(value)) if !value.is_empty() => {
+                expanded.extend(expand(tokenize(value)?, defines, depth + 1, certain)?);
+            }
+            known => {
+                *certain &= known.is_some();
+                if tokens.peek() == Some(&Token::Operator("(")) {
+                    let mut nesting = 0;
+                    for token in tokens.by_ref() {
+                        match token {
+                            Token::Operator("(") => nesting += 1,
+                            Token::Operator(")") => {
+                                nesting -= 1;
+                                if nesting == 0 {
+                                    break;
+                                }
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                expanded.push(Token::Number(0));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        if self.peek() == Some(operator) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("expected `{}` in expression", operator))
+        }
+    }
+
+    fn conditional(&mut self) -> Result<i64, String> {
+        let condition = self.binary(0)?;
+        if self.peek() != Some("?") {
+            return Ok(condition);
+        }
+        self.position += 1;
+        let then = self.conditional()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: [&[&str]; 10] = [
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", ">", "<=", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(operator) = self.peek().filter(|op| LEVELS[level].contains(op)) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = match operator {
+                "||" => (left != 0 || right != 0) as i64,
+                "&&" => (left != 0 && right != 0) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                ">" => (left > right) as i64,
+                "<=" => (left <= right) as i64,
+                ">=" => (left >= right) as i64,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" | "%" if right == 0 => return Err("division by zero in expression".to_string()),
+                "/" => left.wrapping_div(right),
+                "%" => left.wrapping_rem(right),
+                _ => unreachable!(),
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek() {
+            Some("!") => {
+                self.position += 1;
+                Ok((self.unary()? == 0) as i64)
+            }
+            Some("~") => {
+                self.position += 1;
+                Ok(!self.unary()?)
+            }
+            Some("-") => {
+                self.position += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some("+") => {
+                self.position += 1;
+                self.unary()
+            }
+            Some("(") => {
+                self.position += 1;
+                let value = self.conditional()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => match self.tokens.get(self.position) {
+                Some(Token::Number(value)) => {
+                    self.position += 1;
+                    Ok(*value)
+                }
+                Some(Token::Other(c)) => Err(format!("unexpected `{}` in expression", c)),
+                Some(token) => Err(format!("unexpected {:?} in expression", token)),
+                None => Err("unexpected end of expression".to_string()),
+            },
+        }
+    }
+}
+
+/// Evaluates the controlling expression of an `#if` or `#elif` directive, and whether its
+/// value follows from the known macros alone.
+fn evaluate(expression: &str, defines: &Defines) -> Result<(i64, bool), String> {
+    let mut certain = true;
+    let tokens = expand(tokenize(expression)?, defines, 0, &mut certain)?;
+    if tokens.is_empty() {
+        return Err("#if with no expression".to_string());
+    }
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let value = parser.conditional()?;
+    if parser.position != parser.tokens.len() {
+        return Err("unexpected tokens at the end of the expression".to_string());
+    }
+    Ok((value, certain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(line: usize, name: &str) -> IncludeDirective {
+        IncludeDirective {
+            line,
+            kind: IncludeKind::Local(name.to_string()),
+        }
+    }
+
+    fn system(line: usize, name: &str) -> IncludeDirective {
+        IncludeDirective {
+            line,
+            kind: IncludeKind::System(name.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_scan_whitespace_and_comments() {
+        let source = r##"#include "a.h"
+  #  include   <stdio.h>
+// #include "commented.h"
+/* #include "block.h"
+#include "still_block.h" */ #include "after_block.h"
+#include /* inline */ "b.h" // trailing
+#define STR "#include \"not.h\""
+int x; /*
+*/ #include "not_a_directive.h"
+"##;
+        assert_eq!(
+            scan(source, "main.c", &Defines::new()).unwrap(),
+            [
+                local(1, "a.h"),
+                system(2, "stdio.h"),
+                local(4, "after_block.h"),
+                local(6, "b.h")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_conditionals() {
+        let source = r#"#if 0
+#include "disabled.h"
+#elif defined(USE_A) && VERSION >= 2
+#include "a.h"
+#else
+#include "fallback.h"
+#endif
+#ifndef GUARD_H
+#define GUARD_H
+#include "guarded.h"
+#endif
+#ifdef GUARD_H
+#include "after_guard.h"
+#endif
+"#;
+        let mut defines = Defines::new();
+        assert_eq!(
+            scan(source, "main.c", &defines).unwrap(),
+            [
+                local(6, "fallback.h"),
+                local(10, "guarded.h"),
+                local(13, "after_guard.h")
+            ]
+        );
+
+        defines.insert("USE_A".to_string(), None);
+        defines.insert("VERSION".to_string(), Some("3".to_string()));
+        defines.insert("GUARD_H".to_string(), None);
+        assert_eq!(
+            scan(source, "main.c", &defines).unwrap(),
+            [local(4, "a.h"), local(13, "after_guard.h")]
+        );
+    }
+
+    #[test]
+    fn test_scan_nested_inactive() {
+        let source = "#if 0\n#if 1/0\n#include \"a.h\"\n#endif\n#else\n#include \"b.h\"\n#endif\n";
+        assert_eq!(
+            scan(source, "main.c", &Defines::new()).unwrap(),
+            [local(6, "b.h")]
+        );
+    }
+
+    #[test]
+    fn test_scan_macro_include() {
+        let source = "#define CONFIG_H \"config.h\"\n#include CONFIG_H\n#include PLATFORM_H\n";
+        let mut defines = Defines::new();
+        defines.insert("PLATFORM_H".to_string(), Some("<linux.h>".to_string()));
+        assert_eq!(
+            scan(source, "main.c", &defines).unwrap(),
+            [local(2, "config.h"), system(3, "linux.h")]
+        );
+    }
+
+    #[test]
+    fn test_scan_line_continuation() {
+        let source = "#include \\\n  \"a.h\"\n#include \"b.h\"\n";
+        assert_eq!(
+            scan(source, "main.c", &Defines::new()).unwrap(),
+            [local(1, "a.h"), local(3, "b.h")]
+        );
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        assert_eq!(
+            scan("\n#include \"a.h\n", "main.c", &Defines::new()).unwrap_err(),
+            "main.c:2: malformed #include \"a.h"
+        );
+        assert_eq!(
+            scan("#include UNKNOWN\n", "main.c", &Defines::new()).unwrap_err(),
+            "main.c:1: #include UNKNOWN does not expand to a header name"
+        );
+        assert_eq!(
+            scan("#if 1\n#include <a.h>\n", "a.c", &Defines::new()).unwrap_err(),
+            "a.c:1: unterminated conditional directive"
+        );
+        assert_eq!(
+            scan("#endif\n", "a.c", &Defines::new()).unwrap_err(),
+            "a.c:1: #endif without #if"
+        );
+        assert_eq!(
+            scan("#if 1 +\n#endif\n", "a.c", &Defines::new()).unwrap_err(),
+            "a.c:1: unexpected end of expression"
+        );
+    }
+
+    #[test]
+    fn test_get_lines() {
+        let source = "int a; // a\n#if 0\nint b;\n#else\nint /* c */ c;\n#endif\n";
+        assert_eq!(
+            get_lines(source, "main.c", &Defines::new()).unwrap(),
+            [
+                (1, "int a; ".to_string(), Activity::Active),
+                (3, "int b;".to_string(), Activity::Inactive),
+                (5, "int   c;".to_string(), Activity::Active)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_lines_unknown() {
+        let source = r#"#if __STDC_VERSION__ >= 201112L
+a
+#else
+b
+#endif
+#ifdef _WIN32
+c
+#elif 1
+d
+#endif
+#ifndef LOCAL
+#define LOCAL
+g
+#endif
+#ifdef LOCAL
+h
+#endif
+"#;
+        let activities = |defines: &Defines| {
+            get_lines(source, "main.c", defines)
+                .unwrap()
+                .into_iter()
+                .map(|(_, text, activity)| (text, activity))
+                .collect::<Vec<_>>()
+        };
+        let line = |text: &str, activity| (text.to_string(), activity);
+        assert_eq!(
+            activities(&Defines::new()),
+            [
+                line("a", Activity::Unknown),
+                line("b", Activity::Unknown),
+                line("c", Activity::Unknown),
+                line("d", Activity::Unknown),
+                line("g", Activity::Unknown),
+                line("h", Activity::Active)
+            ]
+        );
+
+        let mut defines = Defines::new();
+        defines.insert("__STDC_VERSION__".to_string(), Some("201710L".to_string()));
+        defines.insert("_WIN32".to_string(), None);
+        assert_eq!(
+            activities(&defines)[..4],
+            [
+                line("a", Activity::Active),
+                line("b", Activity::Inactive),
+                line("c", Activity::Active),
+                line("d", Activity::Inactive)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_output_lines() {
+        let output = r#"# 1 "tests/a.c"
+# 1 "<built-in>"
+int builtin;
+# 1 "tests/a.c"
+# 1 "tests/test_framework.h" 1
+int header;
+# 2 "tests/a.c" 2
+
+void test_a(void) {}
+#line 10 "tests/a.c"
+void test_b(void) {}
+"#;
+        assert_eq!(
+            get_output_lines(output, "tests/a.c"),
+            BTreeSet::from([3, 10])
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut defines = Defines::new();
+        defines.insert("A".to_string(), Some("2".to_string()));
+        defines.insert("B".to_string(), Some("A * 3".to_string()));
+        defines.insert("EMPTY".to_string(), None);
+
+        assert_eq!(evaluate("1 + 2 * 3", &defines), Ok((7, true)));
+        assert_eq!(evaluate("(1 + 2) * 3", &defines), Ok((9, true)));
+        assert_eq!(evaluate("B == 6 && defined EMPTY", &defines), Ok((1, true)));
+        assert_eq!(
+            evaluate("!defined(MISSING) ? 0x10 : 1", &defines),
+            Ok((16, false))
+        );
+        assert_eq!(evaluate("MISSING || 1 << 2 >= 4", &defines), Ok((1, false)));
+        assert_eq!(
+            evaluate("__has_include(<x.h>) || -1 < 0", &defines),
+            Ok((1, false))
+        );
+        assert_eq!(evaluate("201112L >= 199901L", &defines), Ok((1, true)));
+        assert_eq!(evaluate("'a' == 97", &defines), Ok((1, true)));
+        assert!(evaluate("1 / 0", &defines).is_err());
+        assert!(evaluate("", &defines).is_err());
+    }
+}
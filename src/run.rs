@@ -1,31 +1,41 @@
-use crate::build::{build, get_build_options, get_target, Config};
+use crate::build::{build, get_build_options, get_target, select_binaries, Binary, Config};
 use crate::cli::{Build, Run};
-use crate::command;
+use crate::command::Command;
+
+fn get_binary(config: &Config, args: &Run) -> Result<Binary, String> {
+    let mut binaries = select_binaries(config, &args.bin)?;
+    match binaries.len() {
+        0 => Err("No binary to run".to_string()),
+        1 => Ok(binaries.remove(0)),
+        _ => Err(format!(
+            "Could not determine which binary to run, use `--bin` to pick one of: {}",
+            binaries
+                .iter()
+                .map(|binary| binary.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )),
+    }
+}
 
 pub fn run(args: &Run) -> Result<String, String> {
     let build_args = Build {
         release: args.release,
         benchmark: args.benchmark,
+        profile: args.profile.clone(),
         asm: false,
+        bin: args.bin.clone(),
     };
 
-    build(&build_args)?;
-
     let config = get_build_options(&build_args)?;
+    let binary = get_binary(&config, args)?;
 
-    let command = if args.benchmark {
-        format!("{}/benchmark", get_target(&config))
-    } else {
-        format!("{}/{}", get_target(&config), &config.package.name)
-    };
+    build(&build_args)?;
+
+    let command = Command::new(format!("{}/{}", get_target(&config), binary.name));
 
     println!("Running {}", command);
-    let mut process = match command::spawn(&command) {
-        Ok(process) => process,
-        Err(error) => {
-            return Err(format!("Failed to run command: {}", error));
-        }
-    };
+    let mut process = command.spawn()?;
 
     match process.wait() {
         Ok(_) => {
@@ -39,59 +49,52 @@ pub fn run(args: &Run) -> Result<String, String> {
     }
 }
 
-pub fn get_memory_string(config: &Config) -> String {
-    let mut memory_string = String::new();
+pub fn get_memory_args(config: &Config) -> Vec<String> {
+    let mut memory_args = Vec::new();
 
     if !config.memory.leak_check.is_empty() {
-        memory_string.push_str("--leak-check=");
-        memory_string.push_str(&config.memory.leak_check);
+        memory_args.push(format!("--leak-check={}", config.memory.leak_check));
     }
 
     if !config.memory.show_leak_kinds.is_empty() {
-        memory_string.push_str(" --show-leak-kinds=");
-        memory_string.push_str(&config.memory.show_leak_kinds);
+        memory_args.push(format!(
+            "--show-leak-kinds={}",
+            config.memory.show_leak_kinds
+        ));
     }
 
     if config.memory.track_origins {
-        memory_string.push_str(" --track-origins=yes");
+        memory_args.push("--track-origins=yes".to_string());
     } else {
-        memory_string.push_str(" --track-origins=no");
+        memory_args.push("--track-origins=no".to_string());
     }
 
-    memory_string
+    memory_args
 }
 
 pub fn memory_run(args: &Run) -> Result<String, String> {
     let build_args = Build {
         release: args.release,
         benchmark: args.benchmark,
+        profile: args.profile.clone(),
         asm: false,
+        bin: args.bin.clone(),
     };
 
-    build(&build_args)?;
-
     let config = get_build_options(&build_args)?;
+    let binary = get_binary(&config, args)?;
 
-    let memory_string = get_memory_string(&config);
+    build(&build_args)?;
 
-    let command = format!(
-        "valgrind {} ./{}/{}",
-        memory_string,
+    let mut command = Command::new("valgrind");
+    command.args(get_memory_args(&config)).arg(format!(
+        "./{}/{}",
         get_target(&config),
-        if args.benchmark {
-            "benchmark"
-        } else {
-            &config.package.name
-        }
-    );
+        binary.name
+    ));
 
     println!("Running {}", command);
-    let mut process = match command::spawn(&command) {
-        Ok(process) => process,
-        Err(error) => {
-            return Err(format!("Failed to run command: {}", error));
-        }
-    };
+    let mut process = command.spawn()?;
 
     match process.wait() {
         Ok(_) => {
@@ -108,25 +111,26 @@ pub fn memory_run(args: &Run) -> Result<String, String> {
 fn print_benchmark(config: &Config) -> Result<String, String> {
     println!("--------------------------------------------------------");
 
-    match command::spawn(&format!("gprof --brief {}/benchmark", get_target(config))) {
-        Ok(mut process) => match process.wait() {
-            Ok(_) => Ok("".to_string()),
-            Err(e) => Err(format!("Failed to wait for command: {}", e)),
-        },
-        Err(error) => Err(format!("Failed to run command: {}", error)),
+    let mut process = Command::new("gprof")
+        .arg("--brief")
+        .arg(format!("{}/benchmark", get_target(config)))
+        .spawn()?;
+    match process.wait() {
+        Ok(_) => Ok("".to_string()),
+        Err(e) => Err(format!("Failed to wait for command: {}", e)),
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::build::{Memory, Mode};
+    use crate::build::Memory;
 
     #[test]
-    fn test_get_memory_string() {
+    fn test_get_memory_args() {
         let config = Config {
             package: Default::default(),
-            mode: Some(Mode::Debug),
+            profile: "debug".to_string(),
             benchmark: Some(false),
             debug: Default::default(),
             release: Default::default(),
@@ -135,20 +139,25 @@ mod test {
                 show_leak_kinds: "definite".to_string(),
                 track_origins: true,
             },
+            ..Default::default()
         };
 
-        let memory_string = get_memory_string(&config);
+        let memory_args = get_memory_args(&config);
         assert_eq!(
-            memory_string,
-            "--leak-check=full --show-leak-kinds=definite --track-origins=yes"
+            memory_args,
+            [
+                "--leak-check=full",
+                "--show-leak-kinds=definite",
+                "--track-origins=yes"
+            ]
         );
     }
 
     #[test]
-    fn test_get_memory_string_empty() {
+    fn test_get_memory_args_empty() {
         let config = Config {
             package: Default::default(),
-            mode: Some(Mode::Debug),
+            profile: "debug".to_string(),
             benchmark: Some(false),
             debug: Default::default(),
             release: Default::default(),
@@ -157,9 +166,10 @@ mod test {
                 show_leak_kinds: "".to_string(),
                 track_origins: false,
             },
+            ..Default::default()
         };
 
-        let memory_string = get_memory_string(&config);
-        assert_eq!(memory_string, " --track-origins=no");
+        let memory_args = get_memory_args(&config);
+        assert_eq!(memory_args, ["--track-origins=no"]);
     }
 }
@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const TARGET_DIR: &str = "c_target";
+
+fn is_pattern(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// Matches a single path component against a pattern component supporting `*`, `?`
+/// and character classes like `[a-z]` or `[!_]`.
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches_component(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_component(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let end = match pattern.iter().skip(2).position(|&c| c == ']') {
+                Some(end) => end + 2,
+                None => {
+                    return name.first() == Some(&'[')
+                        && matches_component(&pattern[1..], &name[1..])
+                }
+            };
+            let c = match name.first() {
+                Some(&c) => c,
+                None => return false,
+            };
+            let (negated, class) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            let mut found = false;
+            let mut index = 0;
+            while index < class.len() {
+                if index + 2 < class.len() && class[index + 1] == '-' {
+                    found |= class[index] <= c && c <= class[index + 2];
+                    index += 3;
+                } else {
+                    found |= class[index] == c;
+                    index += 1;
+                }
+            }
+            found != negated && matches_component(&pattern[end + 1..], &name[1..])
+        }
+        Some(&p) => name.first() == Some(&p) && matches_component(&pattern[1..], &name[1..]),
+    }
+}
+
+fn matches_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(&"**") => {
+            (0..=path.len()).any(|skip| matches_components(&pattern[1..], &path[skip..]))
+        }
+        Some(component) => match path.first() {
+            Some(name) => {
+                matches_component(
+                    &component.chars().collect::<Vec<char>>(),
+                    &name.chars().collect::<Vec<char>>(),
+                ) && matches_components(&pattern[1..], &path[1..])
+            }
+            None => false,
+        },
+    }
+}
+
+fn split(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
+
+/// Checks if a path relative to the project directory matches a glob pattern.
+/// `**` matches any number of directories.
+pub fn matches(pattern: &str, path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    matches_components(&split(pattern), &split(&path))
+}
+
+/// Checks if a name like a test name matches a pattern without directories.
+pub fn matches_name(pattern: &str, name: &str) -> bool {
+    matches_component(
+        &pattern.chars().collect::<Vec<char>>(),
+        &name.chars().collect::<Vec<char>>(),
+    )
+}
+
+/// Collects the files below `dir`. Directories are followed through symlinks, but only
+/// once, so symlink loops end.
+fn walk(
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<(), String> {
+    if let Ok(canonical) = dir.canonicalize() {
+        if !visited.insert(canonical) {
+            return Ok(());
+        }
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(format!(
+                "Failed to read directory `{}`: {}",
+                dir.to_string_lossy(),
+                e
+            ))
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => return Err(format!("Failed to read directory entry: {}", e)),
+        };
+        let path = entry.path();
+        let name = entry.file_name();
+        if path.is_dir() {
+            if name != TARGET_DIR && !name.to_string_lossy().starts_with('.') {
+                walk(&path, files, visited)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the files in the project directory matching any of `sources` but none of
+/// `exclude`, sorted and relative to the project directory.
+pub fn find_sources(sources: &[String], exclude: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for pattern in sources {
+        let root: PathBuf = split(pattern)
+            .into_iter()
+            .take_while(|component| !is_pattern(component))
+            .collect();
+        if !is_pattern(pattern) {
+            if root.is_file() {
+                files.push(root);
+                continue;
+            }
+            return Err(format!("Source file `{}` not found", pattern));
+        }
+        if root.as_os_str().is_empty() {
+            walk(Path::new("."), &mut files, &mut HashSet::new())?;
+        } else if root.is_dir() {
+            walk(&root, &mut files, &mut HashSet::new())?;
+        }
+    }
+
+    let mut files: Vec<PathBuf> = files
+        .into_iter()
+        .map(|file| {
+            file.strip_prefix("./")
+                .map(Path::to_path_buf)
+                .unwrap_or(file)
+        })
+        .filter(|file| sources.iter().any(|pattern| matches(pattern, file)))
+        .filter(|file| !exclude.iter().any(|pattern| matches(pattern, file)))
+        .collect();
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("src/*.c", Path::new("src/main.c")));
+        assert!(!matches("src/*.c", Path::new("src/net/util.c")));
+        assert!(matches("src/**/*.c", Path::new("src/main.c")));
+        assert!(matches("src/**/*.c", Path::new("src/net/util.c")));
+        assert!(!matches("src/**/*.c", Path::new("src/net/util.h")));
+        assert!(matches("**/test_?.c", Path::new("a/b/test_1.c")));
+        assert!(matches("src/[a-c]*.c", Path::new("src/b.c")));
+        assert!(!matches("src/[!a-c]*.c", Path::new("src/b.c")));
+        assert!(matches("./src/main.c", Path::new("src/main.c")));
+        assert!(matches_name("parse_*", "parse_number"));
+        assert!(!matches_name("parse_*", "test_parse"));
+    }
+
+    #[test]
+    fn test_find_sources() {
+        let sources = find_sources(
+            &["examples/fib/**/*.c".to_string()],
+            &["examples/fib/tests/**".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            sources,
+            [
+                PathBuf::from("examples/fib/benchmark/main.c"),
+                PathBuf::from("examples/fib/src/fib.c"),
+                PathBuf::from("examples/fib/src/main.c"),
+            ]
+        );
+
+        assert!(find_sources(&["missing.c".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_walk_symlink_loop() {
+        let root = std::env::temp_dir().join("c-builder-test-symlink-loop");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.c"), "").unwrap();
+        std::os::unix::fs::symlink("..", root.join("src/loop")).unwrap();
+
+        let mut files = Vec::new();
+        walk(&root, &mut files, &mut HashSet::new()).unwrap();
+        assert_eq!(files, [root.join("src/main.c")]);
+    }
+}
@@ -1,49 +1,178 @@
-use super::test_framework;
+use super::test_framework::{self, Tests};
 
 use crate::build::{
     build_object_files, create_output_directory, generate_build_command, get_build_options,
+    get_includes, get_scan_options, get_target, Config,
 };
 use crate::cli::{Build, Test};
-use crate::command::output;
-use crate::includes::{get_includes_from_file, Include};
+use crate::command::Command;
+use crate::includes::{get_includes_from_file, Include, IncludeType, ScanOptions};
 
-fn get_test_includes(test: &Test) -> Result<Vec<Include>, String> {
+use std::path::{Path, PathBuf};
+
+/// Lets test files in subdirectories of `tests` include `test_framework.h`. Only the test
+/// runners are compiled with it, so the objects of the project are shared with `build`.
+pub const TEST_INCLUDE_FLAG: &str = "-Itests";
+
+fn get_test_scan_options(config: &Config) -> ScanOptions {
+    let mut options = get_scan_options(config);
+    options.include_dirs.push(PathBuf::from("tests"));
+    options
+}
+
+fn get_file_includes(
+    test_files: &[PathBuf],
+    runner: &Path,
+    config: &Config,
+) -> Result<Vec<Include>, String> {
+    let options = get_test_scan_options(config);
     let mut includes = Vec::new();
     let mut include_strings = Vec::new();
-    let tests = test_framework::get_tests(test);
-    for test in tests.test_files {
+    for test in test_files {
         includes.append(&mut get_includes_from_file(
             test.parent().unwrap(),
             test.file_name().unwrap().to_string_lossy().to_string(),
             &mut include_strings,
+            &options,
         )?);
     }
+
+    // With `sources` set only the helpers next to the tests, like the test framework,
+    // are taken from the includes.
+    if !config.package.sources.is_empty() {
+        let tests = Path::new("tests").canonicalize().ok();
+        includes.retain(|include| match (&include.kind, &tests) {
+            (IncludeType::Local(path), Some(tests)) => path.starts_with(tests),
+            _ => false,
+        });
+        includes.append(&mut get_includes(config, runner, &options)?);
+    }
+
     includes.sort();
     includes.dedup();
     Ok(includes)
 }
 
-pub fn build(build: &Build, test: &Test) -> Result<Option<String>, String> {
-    let config = get_build_options(build)?;
+/// The includes of every test file. The tests in them are not looked at, so a test file
+/// that fails to parse does not fail the callers.
+pub fn get_test_includes(config: &Config) -> Result<Vec<Include>, String> {
+    get_file_includes(
+        &test_framework::get_test_files()?,
+        Path::new("tests/tests.c"),
+        config,
+    )
+}
 
-    println!("Building tests...");
-    create_output_directory(&config)?;
-    test_framework::write_tests_to_file(test);
+/// The path of the test binary running the tests of the file, relative to the target.
+pub fn get_binary_name(test: &Test, config: &Config, file: &Path) -> String {
+    if config.test.per_file {
+        let name = file
+            .strip_prefix("tests")
+            .unwrap_or(file)
+            .with_extension("");
+        return format!("tests/{}", name.to_string_lossy());
+    }
+    match &test.single {
+        Some(single) => single.clone(),
+        None => "test".to_string(),
+    }
+}
 
-    let includes = get_test_includes(test)?;
-    build_object_files(&includes, &config)?;
+/// The generated file that includes the test file and runs its tests.
+pub fn get_runner(test: &Test, config: &Config, file: &Path) -> PathBuf {
+    if config.test.per_file {
+        PathBuf::from(format!(
+            "{}/{}.c",
+            get_target(config),
+            get_binary_name(test, config, file)
+        ))
+    } else {
+        PathBuf::from("tests/tests.c")
+    }
+}
 
-    let main_file = "tests/tests.c";
-    let command = generate_build_command(&includes, &config, main_file, Some(test));
+fn link(mut command: Command) -> Result<(), String> {
+    command.arg(TEST_INCLUDE_FLAG);
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(String::from("Failed to build tests")),
+        Err(e) => Err(format!("Failed to build tests: {}", e)),
+    }
+}
 
-    match output(&command) {
-        Ok(status) => {
-            if status.success() {
-                Ok(Some(String::from("Tests built successfully")))
-            } else {
-                Err(String::from("Failed to build tests"))
+/// Builds a binary for every test file with selected tests, so test files do not share a
+/// translation unit. The runners are generated in the target directory.
+fn build_per_file(test: &Test, tests: &Tests, config: &Config) -> Result<(), String> {
+    let mut runners = Vec::new();
+    let mut all_includes = Vec::new();
+    for file in &tests.test_files {
+        let file_tests = tests.get_file_tests(file);
+        if file_tests.tests.is_empty() {
+            continue;
+        }
+
+        let name = get_binary_name(test, config, file);
+        let runner = get_runner(test, config, file);
+        if let Some(parent) = runner.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                return Err(format!("Failed to create output directory: {}", e));
             }
         }
-        Err(e) => Err(format!("Failed to build tests: {}", e)),
+        test_framework::write_tests_to_file(&runner, &file_tests)?;
+
+        let includes = get_file_includes(std::slice::from_ref(file), &runner, config)?;
+        all_includes.extend(includes.iter().cloned());
+        runners.push((runner, name, includes));
+    }
+
+    all_includes.sort();
+    all_includes.dedup();
+    build_object_files(&all_includes, config, false)?;
+
+    let directory = match std::env::current_dir().and_then(|dir| dir.canonicalize()) {
+        Ok(directory) => directory,
+        Err(e) => return Err(format!("Failed to get current directory: {}", e)),
+    };
+    for (runner, name, includes) in runners {
+        let mut command =
+            generate_build_command(&includes, config, &runner.to_string_lossy(), &name);
+        // The runners include the test files by their absolute path, which would otherwise
+        // end up in the failure messages.
+        command.arg(format!(
+            "-fmacro-prefix-map={}/=",
+            directory.to_string_lossy()
+        ));
+        link(command)?;
+    }
+    Ok(())
+}
+
+/// Builds the test binaries, returning the tests they run.
+pub fn build(build: &Build, test: &Test) -> Result<Tests, String> {
+    let config = get_build_options(build)?;
+
+    println!("Building tests...");
+    create_output_directory(&config)?;
+    test_framework::create_test_framework(".")?;
+    let tests = test_framework::get_tests(test, &config)?;
+
+    if config.test.per_file {
+        build_per_file(test, &tests, &config)?;
+        return Ok(tests);
     }
+
+    let main_file = Path::new("tests/tests.c");
+    test_framework::write_tests_to_file(main_file, &tests)?;
+
+    let includes = get_file_includes(&tests.test_files, main_file, &config)?;
+    build_object_files(&includes, &config, false)?;
+
+    let output = get_binary_name(test, &config, main_file);
+    link(generate_build_command(
+        &includes,
+        &config,
+        &main_file.to_string_lossy(),
+        &output,
+    ))?;
+    Ok(tests)
 }
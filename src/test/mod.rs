@@ -1,3 +1,4 @@
-mod build;
+pub mod build;
+pub mod report;
 pub mod run;
 pub mod test_framework;
@@ -0,0 +1,294 @@
+use super::run::{TestResult, TestStatus};
+
+use crate::cli::ReportFormat;
+
+use std::fmt::Write;
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    name: &'a str,
+    file: &'a str,
+    status: &'a str,
+    duration: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    output: &'a str,
+}
+
+fn get_status(result: &TestResult) -> &'static str {
+    match result.status {
+        TestStatus::Passed => "passed",
+        TestStatus::Failed(_) => "failed",
+        TestStatus::Crashed(_) => "crashed",
+        TestStatus::TimedOut(_) => "timed_out",
+    }
+}
+
+fn get_message(result: &TestResult) -> Option<String> {
+    match &result.status {
+        TestStatus::Passed => None,
+        TestStatus::Failed(message) => Some(message.clone()),
+        TestStatus::Crashed(signal) => Some(format!("crashed ({})", signal)),
+        TestStatus::TimedOut(timeout) => {
+            Some(format!("timed out after {} ms", timeout.as_millis()))
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() && c != '\n' && c != '\t' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Failed tests are reported as `<failure>`, and crashed or timed out tests as `<error>`.
+fn get_junit(suite: &str, results: &[TestResult]) -> String {
+    let failures = results
+        .iter()
+        .filter(|result| matches!(result.status, TestStatus::Failed(_)))
+        .count();
+    let errors = results
+        .iter()
+        .filter(|result| {
+            matches!(
+                result.status,
+                TestStatus::Crashed(_) | TestStatus::TimedOut(_)
+            )
+        })
+        .count();
+    let time: f64 = results
+        .iter()
+        .map(|result| result.duration.as_secs_f64())
+        .sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+        results.len(),
+        failures,
+        errors,
+        time
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+        escape_xml(suite),
+        results.len(),
+        failures,
+        errors,
+        time
+    )
+    .unwrap();
+
+    for result in results {
+        let classname = result
+            .file
+            .strip_suffix(".c")
+            .unwrap_or(&result.file)
+            .replace('/', ".");
+        write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" time=\"{:.3}\"",
+            escape_xml(&result.name),
+            escape_xml(&classname),
+            escape_xml(&result.file),
+            result.duration.as_secs_f64()
+        )
+        .unwrap();
+        if result.status == TestStatus::Passed && result.output.is_empty() {
+            xml.push_str("/>\n");
+            continue;
+        }
+        xml.push_str(">\n");
+
+        let message = escape_xml(&get_message(result).unwrap_or_default());
+        match &result.status {
+            TestStatus::Passed => (),
+            TestStatus::Failed(_) => writeln!(
+                xml,
+                "      <failure message=\"{}\">{}</failure>",
+                message, message
+            )
+            .unwrap(),
+            TestStatus::Crashed(signal) => writeln!(
+                xml,
+                "      <error message=\"{}\" type=\"{}\"/>",
+                message,
+                escape_xml(signal)
+            )
+            .unwrap(),
+            TestStatus::TimedOut(_) => writeln!(
+                xml,
+                "      <error message=\"{}\" type=\"timeout\"/>",
+                message
+            )
+            .unwrap(),
+        }
+        if !result.output.is_empty() {
+            writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape_xml(&result.output)
+            )
+            .unwrap();
+        }
+        xml.push_str("    </testcase>\n");
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+fn get_tap(results: &[TestResult]) -> String {
+    let mut tap = format!("TAP version 13\n1..{}\n", results.len());
+    for (index, result) in results.iter().enumerate() {
+        let ok = if result.status == TestStatus::Passed {
+            "ok"
+        } else {
+            "not ok"
+        };
+        writeln!(tap, "{} {} - {}", ok, index + 1, result.name).unwrap();
+        if let Some(message) = get_message(result) {
+            tap.push_str("  ---\n");
+            writeln!(tap, "  message: {:?}", message).unwrap();
+            writeln!(tap, "  severity: {}", get_status(result)).unwrap();
+            writeln!(tap, "  file: {:?}", result.file).unwrap();
+            writeln!(tap, "  duration_ms: {}", result.duration.as_millis()).unwrap();
+            tap.push_str("  ...\n");
+        }
+    }
+    tap
+}
+
+fn get_json(results: &[TestResult]) -> Result<String, String> {
+    let records: Vec<Record> = results
+        .iter()
+        .map(|result| Record {
+            name: &result.name,
+            file: &result.file,
+            status: get_status(result),
+            duration: result.duration.as_secs_f64(),
+            message: get_message(result),
+            output: &result.output,
+        })
+        .collect();
+    match serde_json::to_string_pretty(&records) {
+        Ok(json) => Ok(json + "\n"),
+        Err(e) => Err(format!("Failed to serialize test report: {}", e)),
+    }
+}
+
+pub fn get_report(
+    format: &ReportFormat,
+    suite: &str,
+    results: &[TestResult],
+) -> Result<String, String> {
+    match format {
+        ReportFormat::Junit => Ok(get_junit(suite, results)),
+        ReportFormat::Tap => Ok(get_tap(results)),
+        ReportFormat::Json => get_json(results),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn get_results() -> Vec<TestResult> {
+        let passed = TestResult {
+            name: "add".to_string(),
+            file: "tests/math.c".to_string(),
+            status: TestStatus::Passed,
+            duration: Duration::from_millis(2),
+            output: String::new(),
+        };
+        vec![
+            passed.clone(),
+            TestResult {
+                name: "sub".to_string(),
+                status: TestStatus::Failed("tests/math.c:9: a < b".to_string()),
+                output: "debug".to_string(),
+                ..passed.clone()
+            },
+            TestResult {
+                name: "div".to_string(),
+                status: TestStatus::Crashed("SIGFPE".to_string()),
+                ..passed
+            },
+        ]
+    }
+
+    #[test]
+    fn test_get_junit() {
+        assert_eq!(
+            get_junit("calc", &get_results()),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="3" failures="1" errors="1" time="0.006">
+  <testsuite name="calc" tests="3" failures="1" errors="1" time="0.006">
+    <testcase name="add" classname="tests.math" file="tests/math.c" time="0.002"/>
+    <testcase name="sub" classname="tests.math" file="tests/math.c" time="0.002">
+      <failure message="tests/math.c:9: a &lt; b">tests/math.c:9: a &lt; b</failure>
+      <system-out>debug</system-out>
+    </testcase>
+    <testcase name="div" classname="tests.math" file="tests/math.c" time="0.002">
+      <error message="crashed (SIGFPE)" type="SIGFPE"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn test_get_tap() {
+        assert_eq!(
+            get_tap(&get_results()),
+            r#"TAP version 13
+1..3
+ok 1 - add
+not ok 2 - sub
+  ---
+  message: "tests/math.c:9: a < b"
+  severity: failed
+  file: "tests/math.c"
+  duration_ms: 2
+  ...
+not ok 3 - div
+  ---
+  message: "crashed (SIGFPE)"
+  severity: crashed
+  file: "tests/math.c"
+  duration_ms: 2
+  ...
+"#
+        );
+    }
+
+    #[test]
+    fn test_get_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&get_json(&get_results()).unwrap()).unwrap();
+        assert_eq!(json[0]["status"], "passed");
+        assert!(json[0].get("message").is_none());
+        assert_eq!(json[1]["message"], "tests/math.c:9: a < b");
+        assert_eq!(json[1]["output"], "debug");
+        assert_eq!(json[2]["status"], "crashed");
+        assert_eq!(json[2]["duration"], 0.002);
+    }
+}
@@ -1,61 +1,525 @@
-use super::build;
+use super::build::{self, get_binary_name};
+use super::report;
+use super::test_framework::{TestCase, FAILURE_MARKER};
 
-use crate::build::{get_build_options, get_target};
+use crate::build::{get_build_options, get_target, Config};
 use crate::cli::{Build, Test};
-use crate::command::spawn;
-use crate::run::get_memory_string;
+use crate::command::Command;
+use crate::run::get_memory_args;
 
-fn get_test(test: &Test) -> String {
-    if let Some(single) = &test.single {
-        single.clone()
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Output};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rayon::ThreadPoolBuilder;
+
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+
+/// How long the output of a killed test is still read, for processes that left its process
+/// group and keep the pipes open.
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestStatus {
+    Passed,
+    Failed(String),
+    Crashed(String),
+    TimedOut(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub file: String,
+    pub status: TestStatus,
+    pub duration: Duration,
+    pub output: String,
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.status {
+            TestStatus::Passed => write!(f, "Test `{}` passed", self.name),
+            TestStatus::Failed(message) => write!(
+                f,
+                "Test `{}` failed: {}",
+                self.name,
+                message.replace('\n', "\n    ")
+            ),
+            TestStatus::Crashed(signal) => write!(f, "Test `{}` crashed ({})", self.name, signal),
+            TestStatus::TimedOut(timeout) => write!(
+                f,
+                "Test `{}` timed out after {} ms",
+                self.name,
+                timeout.as_millis()
+            ),
+        }
+    }
+}
+
+fn get_signal_name(signal: i32) -> String {
+    match signal {
+        4 => "SIGILL".to_string(),
+        5 => "SIGTRAP".to_string(),
+        6 => "SIGABRT".to_string(),
+        7 => "SIGBUS".to_string(),
+        8 => "SIGFPE".to_string(),
+        9 => "SIGKILL".to_string(),
+        11 => "SIGSEGV".to_string(),
+        13 => "SIGPIPE".to_string(),
+        15 => "SIGTERM".to_string(),
+        signal => format!("signal {}", signal),
+    }
+}
+
+/// Classifies the outcome of running a single test in its own process. The failure message
+/// is taken out of the captured output, which keeps everything else the test printed.
+fn get_test_result(test: &TestCase, output: &Output, duration: Duration) -> TestResult {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut messages = Vec::new();
+    let mut lines = Vec::new();
+    for line in stdout.lines() {
+        match line.strip_prefix(FAILURE_MARKER) {
+            Some(failure) => messages.push(failure),
+            None => lines.push(line),
+        }
+    }
+    let mut captured = lines.join("\n").trim_end().to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        if !captured.is_empty() {
+            captured.push('\n');
+        }
+        captured.push_str(stderr.trim_end());
+    }
+
+    let status = if let Some(signal) = output.status.signal() {
+        TestStatus::Crashed(get_signal_name(signal))
+    } else if output.status.success() {
+        TestStatus::Passed
+    } else if !messages.is_empty() {
+        TestStatus::Failed(messages.join("\n"))
     } else {
-        "test".to_string()
+        TestStatus::Failed(match output.status.code() {
+            Some(code) => format!("exited with status {}", code),
+            None => "exited abnormally".to_string(),
+        })
+    };
+
+    TestResult {
+        name: test.name.clone(),
+        file: test.file.to_string_lossy().to_string(),
+        status,
+        duration,
+        output: captured,
+    }
+}
+
+/// Reads a pipe in the background. What was read so far can be taken before the pipe is
+/// closed, so a process holding on to it does not block the runner.
+struct Capture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    reader: JoinHandle<()>,
+}
+
+impl Capture {
+    fn new<R: Read + Send + 'static>(pipe: Option<R>) -> Capture {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&buffer);
+        let reader = thread::spawn(move || {
+            let mut pipe = match pipe {
+                Some(pipe) => pipe,
+                None => return,
+            };
+            let mut chunk = [0; 4096];
+            loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(read) => shared.lock().unwrap().extend_from_slice(&chunk[..read]),
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                    Err(_) => break,
+                }
+            }
+        });
+        Capture { buffer, reader }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.reader.is_finished()
+    }
+
+    fn take(self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer.lock().unwrap())
+    }
+}
+
+/// Kills the process group of the child, which also takes down the processes it started.
+fn kill_process_group(child: &mut Child) {
+    // The child leads its process group, so the group has the id of the child.
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+/// Runs the command to completion like `Command::output`, but kills it and the processes it
+/// started once it has run for longer than the timeout. Returns whether it was killed.
+fn get_output_with_timeout(
+    command: &Command,
+    timeout: Option<Duration>,
+) -> Result<(Output, bool), String> {
+    let mut child = command.clone().new_process_group().spawn_piped()?;
+    let stdout = Capture::new(child.stdout.take());
+    let stderr = Capture::new(child.stderr.take());
+
+    let start = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) if stdout.is_finished() && stderr.is_finished() => break status,
+            Ok(_) if timeout.is_some_and(|timeout| start.elapsed() >= timeout) => {
+                timed_out = true;
+                kill_process_group(&mut child);
+                match child.wait() {
+                    Ok(status) => break status,
+                    Err(e) => return Err(format!("Failed to wait for command: {}", e)),
+                }
+            }
+            Ok(_) => thread::sleep(Duration::from_millis(5)),
+            Err(e) => return Err(format!("Failed to wait for command: {}", e)),
+        }
+    };
+
+    if timed_out {
+        let killed = Instant::now();
+        while !(stdout.is_finished() && stderr.is_finished())
+            && killed.elapsed() < KILLED_OUTPUT_GRACE
+        {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    let output = Output {
+        status,
+        stdout: stdout.take(),
+        stderr: stderr.take(),
+    };
+    Ok((output, timed_out))
+}
+
+/// The timeout of `TEST_TIMEOUT` takes precedence over the one in the `[test]` section.
+fn get_timeout(case: &TestCase, config: &Config) -> Option<Duration> {
+    match case
+        .timeout
+        .or(config.test.timeout)
+        .unwrap_or(DEFAULT_TIMEOUT_MS)
+    {
+        0 => None,
+        timeout => Some(Duration::from_millis(timeout)),
     }
 }
 
+fn run_test(
+    case: &TestCase,
+    config: &Config,
+    get_command: impl Fn(&TestCase) -> Command,
+) -> Result<TestResult, String> {
+    let timeout = get_timeout(case, config);
+    let start = Instant::now();
+    let (output, timed_out) = get_output_with_timeout(&get_command(case), timeout)?;
+    let mut result = get_test_result(case, &output, start.elapsed());
+    if let (true, Some(timeout)) = (timed_out, timeout) {
+        result.status = TestStatus::TimedOut(timeout);
+    }
+    Ok(result)
+}
+
+/// Runs every test in a separate process, so a crashing test does not take down the others.
+/// Up to `test.jobs` tests run at once, but results are printed and returned in the order the
+/// tests were discovered.
+fn run_tests(
+    test: &Test,
+    cases: &[TestCase],
+    config: &Config,
+    get_command: impl Fn(&TestCase) -> Command + Sync,
+    show_output: bool,
+) -> Result<Vec<TestResult>, String> {
+    let pool = match ThreadPoolBuilder::new().num_threads(test.jobs).build() {
+        Ok(pool) => pool,
+        Err(e) => return Err(format!("Failed to create test thread pool: {}", e)),
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    pool.in_place_scope(|scope| {
+        for (index, case) in cases.iter().enumerate() {
+            let sender = sender.clone();
+            let get_command = &get_command;
+            scope.spawn(move |_| {
+                let _ = sender.send((index, run_test(case, config, get_command)));
+            });
+        }
+        drop(sender);
+
+        let mut results = Vec::new();
+        let mut finished = BTreeMap::new();
+        for (index, result) in receiver {
+            finished.insert(index, result);
+            while let Some(result) = finished.remove(&results.len()) {
+                let result = result?;
+                println!("{}", result);
+                if !result.output.is_empty() && (show_output || result.status != TestStatus::Passed)
+                {
+                    println!("{}", result.output);
+                }
+                results.push(result);
+            }
+        }
+        Ok(results)
+    })
+}
+
+fn write_report(test: &Test, config: &Config, results: &[TestResult]) -> Result<(), String> {
+    let (format, output) = match (&test.format, &test.output) {
+        (Some(format), Some(output)) => (format, output),
+        _ => return Ok(()),
+    };
+    let report = report::get_report(format, &config.package.name, results)?;
+    match std::fs::write(output, report) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to write test report `{}`: {}", output, e)),
+    }
+}
+
+/// Sums up the results like cargo does, failing when any test did not pass.
+fn get_summary(results: &[TestResult]) -> Result<String, String> {
+    let failed: Vec<&TestResult> = results
+        .iter()
+        .filter(|result| result.status != TestStatus::Passed)
+        .collect();
+    let summary = format!(
+        "{} passed; {} failed",
+        results.len() - failed.len(),
+        failed.len()
+    );
+
+    if failed.is_empty() {
+        return Ok(format!("\ntest result: ok. {}", summary));
+    }
+    let mut message = String::from("\nfailures:\n");
+    for result in failed {
+        message.push_str(&format!("    {}\n", result.name));
+    }
+    Err(format!("{}\ntest result: FAILED. {}", message, summary))
+}
+
 pub fn run(test: &Test) -> Result<String, String> {
     let build = Build {
         release: test.release,
         benchmark: false,
+        profile: test.profile.clone(),
         asm: false,
+        bin: None,
     };
-    build::build(&build, test)?;
+    let tests = build::build(&build, test)?;
 
     let config = get_build_options(&build)?;
-    let command = format!("{}/{}", get_target(&config), get_test(test));
+    let target = get_target(&config);
 
     println!("Running tests...");
-    match spawn(&command) {
-        Ok(mut process) => match process.wait() {
-            Ok(_) => Ok(String::new()),
-            Err(e) => Err(format!("Failed to wait for command: {}", e)),
+    let results = run_tests(
+        test,
+        &tests.tests,
+        &config,
+        |case| {
+            let binary = get_binary_name(test, &config, &case.file);
+            let mut command = Command::new(format!("{}/{}", target, binary));
+            command.arg(&case.name);
+            command
         },
-        Err(e) => Err(format!("Failed to run tests: {}", e)),
-    }
+        false,
+    )?;
+    write_report(test, &config, &results)?;
+    get_summary(&results)
 }
 
 pub fn memory_run(test: &Test) -> Result<String, String> {
     let build = Build {
         release: test.release,
         benchmark: false,
+        profile: test.profile.clone(),
         asm: false,
+        bin: None,
     };
-    build::build(&build, test)?;
+    let tests = build::build(&build, test)?;
 
     let config = get_build_options(&build)?;
-    let command = format!(
-        "valgrind {} {}/{}",
-        get_memory_string(&config),
-        get_target(&config),
-        get_test(test),
-    );
+    let target = get_target(&config);
 
     println!("Running tests with memory check...");
-    match spawn(&command) {
-        Ok(mut process) => match process.wait() {
-            Ok(_) => Ok(String::new()),
-            Err(e) => Err(format!("Failed to wait for command: {}", e)),
+    let results = run_tests(
+        test,
+        &tests.tests,
+        &config,
+        |case| {
+            let binary = get_binary_name(test, &config, &case.file);
+            let mut command = Command::new("valgrind");
+            command
+                .args(get_memory_args(&config))
+                .arg(format!("{}/{}", target, binary))
+                .arg(&case.name);
+            command
         },
-        Err(e) => Err(format!("Failed to run tests: {}", e)),
+        true,
+    )?;
+    write_report(test, &config, &results)?;
+    get_summary(&results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::ExitStatus;
+
+    fn get_case() -> TestCase {
+        TestCase {
+            name: "add".to_string(),
+            file: "tests/a.c".into(),
+            timeout: None,
+        }
+    }
+
+    fn get_output(status: i32, stdout: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(status),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_get_test_result() {
+        let result = get_test_result(&get_case(), &get_output(0, "hello\n"), Duration::ZERO);
+        assert_eq!(result.status, TestStatus::Passed);
+        assert_eq!(result.output, "hello");
+        assert_eq!(result.to_string(), "Test `add` passed");
+
+        let result = get_test_result(
+            &get_case(),
+            &get_output(1 << 8, "partial\nc-builder-failure: tests/a.c:3: 1 == 2\n"),
+            Duration::ZERO,
+        );
+        assert_eq!(
+            result.status,
+            TestStatus::Failed("tests/a.c:3: 1 == 2".to_string())
+        );
+        assert_eq!(result.output, "partial");
+        assert_eq!(result.to_string(), "Test `add` failed: tests/a.c:3: 1 == 2");
+
+        let result = get_test_result(
+            &get_case(),
+            &get_output(
+                1 << 8,
+                "\nc-builder-failure: tests/a.c:3: x != 1\nc-builder-failure: tests/a.c:4: y != 2\n",
+            ),
+            Duration::ZERO,
+        );
+        assert_eq!(
+            result.status,
+            TestStatus::Failed("tests/a.c:3: x != 1\ntests/a.c:4: y != 2".to_string())
+        );
+        assert_eq!(
+            result.to_string(),
+            "Test `add` failed: tests/a.c:3: x != 1\n    tests/a.c:4: y != 2"
+        );
+
+        let result = get_test_result(&get_case(), &get_output(11, ""), Duration::ZERO);
+        assert_eq!(result.to_string(), "Test `add` crashed (SIGSEGV)");
+    }
+
+    #[test]
+    fn test_get_summary() {
+        let passed = TestResult {
+            name: "a".to_string(),
+            file: "tests/a.c".to_string(),
+            status: TestStatus::Passed,
+            duration: Duration::ZERO,
+            output: String::new(),
+        };
+        let crashed = TestResult {
+            name: "b".to_string(),
+            status: TestStatus::Crashed("SIGSEGV".to_string()),
+            ..passed.clone()
+        };
+
+        assert_eq!(
+            get_summary(std::slice::from_ref(&passed)),
+            Ok("\ntest result: ok. 1 passed; 0 failed".to_string())
+        );
+        assert_eq!(
+            get_summary(&[passed, crashed]),
+            Err("\nfailures:\n    b\n\ntest result: FAILED. 1 passed; 1 failed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_get_output_with_timeout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo started; exec sleep 5"]);
+        let start = Instant::now();
+        let (output, timed_out) =
+            get_output_with_timeout(&command, Some(Duration::from_millis(100))).unwrap();
+        assert!(timed_out);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "started\n");
+
+        // Processes started by the test are killed with it.
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo started; sleep 5; echo finished"]);
+        let start = Instant::now();
+        let (output, timed_out) =
+            get_output_with_timeout(&command, Some(Duration::from_millis(100))).unwrap();
+        assert!(timed_out);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "started\n");
+
+        // A process that left the process group does not keep the runner waiting.
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo started; setsid sleep 5"]);
+        let start = Instant::now();
+        let (output, timed_out) =
+            get_output_with_timeout(&command, Some(Duration::from_millis(100))).unwrap();
+        assert!(timed_out);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "started\n");
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo done"]);
+        let (output, timed_out) = get_output_with_timeout(&command, None).unwrap();
+        assert!(!timed_out);
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_get_timeout() {
+        let mut config = Config::default();
+        let mut case = get_case();
+        assert_eq!(
+            get_timeout(&case, &config),
+            Some(Duration::from_millis(DEFAULT_TIMEOUT_MS))
+        );
+
+        config.test.timeout = Some(0);
+        assert_eq!(get_timeout(&case, &config), None);
+
+        case.timeout = Some(250);
+        assert_eq!(
+            get_timeout(&case, &config),
+            Some(Duration::from_millis(250))
+        );
     }
 }
@@ -1,182 +1,868 @@
+use super::build::TEST_INCLUDE_FLAG;
+
+use crate::build::{get_cflags, get_scan_options, Config};
 use crate::cli::Test;
+use crate::command::Command;
+use crate::preprocessor::{self, Activity, Defines};
+use crate::sources;
+use crate::toolchain::get_cc;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct Tests {
     pub test_files: Vec<PathBuf>,
-    pub tests: Vec<String>,
+    pub tests: Vec<TestCase>,
+    /// `SETUP()` and `TEARDOWN()` of every test file that has them.
+    pub fixtures: BTreeMap<PathBuf, Fixtures>,
+    /// `SUITE_SETUP()` and `SUITE_TEARDOWN()` of the test files declaring them.
+    pub suites: BTreeMap<PathBuf, Fixtures>,
+}
+
+impl Tests {
+    /// The suite fixtures run around the tests of every test process.
+    fn get_suite(&self) -> Fixtures {
+        let mut suite = Fixtures::default();
+        for fixtures in self.suites.values() {
+            suite.setup |= fixtures.setup;
+            suite.teardown |= fixtures.teardown;
+        }
+        suite
+    }
+
+    /// The tests of a single file, to build a test binary for it.
+    pub fn get_file_tests(&self, file: &Path) -> Tests {
+        let only_file = |fixtures: &BTreeMap<PathBuf, Fixtures>| {
+            fixtures
+                .iter()
+                .filter(|(path, _)| *path == file)
+                .map(|(path, fixtures)| (path.clone(), fixtures.clone()))
+                .collect()
+        };
+        Tests {
+            test_files: vec![file.to_path_buf()],
+            tests: self
+                .tests
+                .iter()
+                .filter(|case| case.file == file)
+                .cloned()
+                .collect(),
+            fixtures: only_file(&self.fixtures),
+            suites: only_file(&self.suites),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Fixtures {
+    pub setup: bool,
+    pub teardown: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub file: PathBuf,
+    /// Timeout in milliseconds given with `TEST_TIMEOUT(name, ms)`.
+    pub timeout: Option<u64>,
 }
 
+/// Printed by the test runner in front of the message of a failed test.
+pub const FAILURE_MARKER: &str = "c-builder-failure: ";
+
 const TEST_FRAMEWORK_C: &str = r#"#include "test_framework.h"
 
 #include <setjmp.h>
+#include <stdarg.h>
+#include <stdio.h>
 
 jmp_buf jmpbuf;
-char *error_msg;
+char error_msg[ERROR_MSG_SIZE];
+static size_t error_len;
+static int failures;
+
+/* Appends a failure to error_msg, one failure per line. */
+static void record(const char *format, va_list args) {
+    if (error_len > 0 && error_len < sizeof(error_msg) - 1) {
+        error_msg[error_len++] = '\n';
+    }
+    int len = vsnprintf(error_msg + error_len, sizeof(error_msg) - error_len, format, args);
+    if (len > 0) {
+        error_len += len;
+    }
+    if (error_len > sizeof(error_msg) - 1) {
+        error_len = sizeof(error_msg) - 1;
+    }
+    failures++;
+}
 
-void fail(char *msg) {
-    error_msg = msg;
+void fail(const char *msg) {
+    failf("%s", msg);
+}
+
+void failf(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    record(format, args);
+    va_end(args);
     longjmp(jmpbuf, 1);
 }
+
+void expectf(const char *format, ...) {
+    va_list args;
+    va_start(args, format);
+    record(format, args);
+    va_end(args);
+}
+
+void reset_failures(void) {
+    error_msg[0] = '\0';
+    error_len = 0;
+    failures = 0;
+}
+
+void print_failures(const char *prefix) {
+    const char *line = error_msg;
+    while (*line != '\0') {
+        size_t len = strcspn(line, "\n");
+        printf("%s%.*s\n", prefix, (int)len, line);
+        line += line[len] == '\n' ? len + 1 : len;
+    }
+}
+
+int run_step(test_fn *step) {
+    if (step == NULL) {
+        return 0;
+    }
+    int before = failures;
+    if (setjmp(jmpbuf) == 0) {
+        step();
+    }
+    return failures != before;
+}
+
+int run_test(struct Test *test) {
+    reset_failures();
+    int failed = run_step(test->setup) || run_step(test->test);
+    return run_step(test->teardown) || failed;
+}
+
+int run_suite_test(struct Test *suite, struct Test *test) {
+    reset_failures();
+    int failed = run_step(suite->setup) || run_test(test);
+    return run_step(suite->teardown) || failed;
+}
+
+void format_signed_value(char *buffer, long long value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%lld", value);
+}
+
+void format_unsigned_value(char *buffer, unsigned long long value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%llu", value);
+}
+
+void format_float_value(char *buffer, long double value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%Lg", value);
+}
+
+void format_pointer_value(char *buffer, const void *value) {
+    snprintf(buffer, VALUE_STRING_SIZE, "%p", value);
+}
 "#;
 
 const TEST_FRAMEWORK_H: &str = r#"#include <string.h>
 #include <math.h>
 
 #pragma once
+#define TEST_FRAMEWORK_VERSION 1
 #define TEST(name) void test_##name()
+#define TEST_TIMEOUT(name, ms) void test_##name()
 #define TO_STRING(x) #x
 #define STRINGIFY(x) TO_STRING(x)
+#define CONCAT_(a, b) a##b
+#define CONCAT(a, b) CONCAT_(a, b)
+#define LOCATION __FILE__ ":" STRINGIFY(__LINE__) ": "
+#define ERROR_MSG_SIZE 4096
+#define VALUE_STRING_SIZE 64
 
-#define ASSERT(cond)                                                           \
+#define SETUP() void CONCAT(setup_, TEST_FILE_ID)()
+#define TEARDOWN() void CONCAT(teardown_, TEST_FILE_ID)()
+#define SUITE_SETUP() void suite_setup()
+#define SUITE_TEARDOWN() void suite_teardown()
+
+/* Every check reports a failure with `report`, which is `failf` for the ASSERT_* macros
+   and `expectf` for the EXPECT_* macros. */
+#define CHECK(report, cond)                                                    \
     if (!(cond)) {                                                             \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #cond);                     \
+        report(LOCATION "%s", #cond);                                          \
     }
 
-#define ASSERT_EQ(a, b)                                                        \
+#define CHECK_EQ(report, a, b)                                                 \
     if ((a) != (b)) {                                                          \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != " #b);              \
+        report(LOCATION "%s", #a " != " #b);                                   \
     }
 
-#define ASSERT_STRING_EQ(a, b)                                                 \
-    if (strcmp(a, b) != 0) {                                                   \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != " #b);              \
-    }
+#define CHECK_STRING_EQ(report, a, b)                                          \
+    do {                                                                       \
+        const char *actual_ = (a), *expected_ = (b);                           \
+        if (strcmp(actual_, expected_) != 0) {                                 \
+            report(LOCATION "expected " #a " == " #b ", got \"%s\" and \"%s\"",\
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
 
-#define ASSERT_NULL(a)                                                         \
+#define CHECK_NULL(report, a)                                                  \
     if ((a) != NULL) {                                                         \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != NULL");             \
+        report(LOCATION "%s", #a " != NULL");                                  \
     }
 
-#define ASSERT_NOT_NULL(a)                                                     \
+#define CHECK_NOT_NULL(report, a)                                              \
     if ((a) == NULL) {                                                         \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " == NULL");             \
+        report(LOCATION "%s", #a " == NULL");                                  \
     }
-    
-#define ASSERT_FALSE(a)                                                        \
+
+#define CHECK_FALSE(report, a)                                                 \
     if (a) {                                                                   \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " is not false");        \
+        report(LOCATION "%s", #a " is not false");                             \
     }
 
-#define ASSERT_FLOAT_EQ(a, b)                                                  \
-    if (fabs((a) - (b)) > 1e-6) {                                              \
-        fail(__FILE__ ":" STRINGIFY(__LINE__) ": " #a " != " #b);              \
-    }
+#define CHECK_FLOAT_NEAR(report, a, b, tolerance)                              \
+    do {                                                                       \
+        double actual_ = (a), expected_ = (b);                                 \
+        if (!(fabs(actual_ - expected_) <= (tolerance))) {                     \
+            report(LOCATION "expected " #a " == " #b " within " #tolerance     \
+                   ", got %g and %g", actual_, expected_);                     \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_EQ_INT(report, a, b)                                             \
+    do {                                                                       \
+        long long actual_ = (a), expected_ = (b);                              \
+        if (actual_ != expected_) {                                            \
+            report(LOCATION "expected " #a " == " #b ", got %lld and %lld",    \
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_EQ_UINT(report, a, b)                                            \
+    do {                                                                       \
+        unsigned long long actual_ = (a), expected_ = (b);                     \
+        if (actual_ != expected_) {                                            \
+            report(LOCATION "expected " #a " == " #b ", got %llu and %llu",    \
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_EQ_PTR(report, a, b)                                             \
+    do {                                                                       \
+        const void *actual_ = (a), *expected_ = (b);                           \
+        if (actual_ != expected_) {                                            \
+            report(LOCATION "expected " #a " == " #b ", got %p and %p",        \
+                   actual_, expected_);                                        \
+        }                                                                      \
+    } while (0)
+
+#define CHECK_MEM_EQ(report, a, b, size)                                       \
+    do {                                                                       \
+        const unsigned char *actual_ = (const void *)(a);                      \
+        const unsigned char *expected_ = (const void *)(b);                    \
+        size_t size_ = (size);                                                 \
+        for (size_t i_ = 0; i_ < size_; i_++) {                                \
+            if (actual_[i_] != expected_[i_]) {                                \
+                report(LOCATION "expected " #a " == " #b " for %zu bytes, "    \
+                       "byte %zu is 0x%02x and 0x%02x",                        \
+                       size_, i_, actual_[i_], expected_[i_]);                 \
+                break;                                                         \
+            }                                                                  \
+        }                                                                      \
+    } while (0)
+
+/* Writes a value to a buffer of VALUE_STRING_SIZE bytes, formatted by its type. */
+#if defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L
+#define FORMAT_VALUE(buffer, x)                                                \
+    _Generic((x),                                                              \
+        _Bool: format_unsigned_value,                                          \
+        char: format_signed_value,                                             \
+        signed char: format_signed_value,                                      \
+        short: format_signed_value,                                            \
+        int: format_signed_value,                                              \
+        long: format_signed_value,                                             \
+        long long: format_signed_value,                                        \
+        unsigned char: format_unsigned_value,                                  \
+        unsigned short: format_unsigned_value,                                 \
+        unsigned int: format_unsigned_value,                                   \
+        unsigned long: format_unsigned_value,                                  \
+        unsigned long long: format_unsigned_value,                             \
+        float: format_float_value,                                             \
+        double: format_float_value,                                            \
+        long double: format_float_value,                                       \
+        default: format_pointer_value)(buffer, x)
+#else
+/* Before C11 the type of a value can not be told apart. */
+#define FORMAT_VALUE(buffer, x) strcpy(buffer, "?")
+#endif
+
+/* Compares two values of their own types, so pointers and 64 bit integers compare
+   exactly. */
+#define CHECK_COMPARE(report, a, op, b)                                        \
+    do {                                                                       \
+        __typeof__(a) actual_ = (a);                                           \
+        __typeof__(b) expected_ = (b);                                         \
+        if (!(actual_ op expected_)) {                                         \
+            char actual_string_[VALUE_STRING_SIZE];                            \
+            char expected_string_[VALUE_STRING_SIZE];                          \
+            FORMAT_VALUE(actual_string_, actual_);                             \
+            FORMAT_VALUE(expected_string_, expected_);                         \
+            report(LOCATION "expected " #a " " #op " " #b ", got %s and %s",   \
+                   actual_string_, expected_string_);                          \
+        }                                                                      \
+    } while (0)
+
+#define ASSERT(cond) CHECK(failf, cond)
+#define ASSERT_EQ(a, b) CHECK_EQ(failf, a, b)
+#define ASSERT_STRING_EQ(a, b) CHECK_STRING_EQ(failf, a, b)
+#define ASSERT_NULL(a) CHECK_NULL(failf, a)
+#define ASSERT_NOT_NULL(a) CHECK_NOT_NULL(failf, a)
+#define ASSERT_FALSE(a) CHECK_FALSE(failf, a)
+#define ASSERT_FLOAT_EQ(a, b) CHECK_FLOAT_NEAR(failf, a, b, 1e-6)
+#define ASSERT_FLOAT_NEAR(a, b, tolerance) CHECK_FLOAT_NEAR(failf, a, b, tolerance)
+#define ASSERT_EQ_INT(a, b) CHECK_EQ_INT(failf, a, b)
+#define ASSERT_EQ_UINT(a, b) CHECK_EQ_UINT(failf, a, b)
+#define ASSERT_EQ_PTR(a, b) CHECK_EQ_PTR(failf, a, b)
+#define ASSERT_MEM_EQ(a, b, size) CHECK_MEM_EQ(failf, a, b, size)
+#define ASSERT_NE(a, b) CHECK_COMPARE(failf, a, !=, b)
+#define ASSERT_LT(a, b) CHECK_COMPARE(failf, a, <, b)
+#define ASSERT_LE(a, b) CHECK_COMPARE(failf, a, <=, b)
+#define ASSERT_GT(a, b) CHECK_COMPARE(failf, a, >, b)
+#define ASSERT_GE(a, b) CHECK_COMPARE(failf, a, >=, b)
+
+#define EXPECT(cond) CHECK(expectf, cond)
+#define EXPECT_EQ(a, b) CHECK_EQ(expectf, a, b)
+#define EXPECT_STRING_EQ(a, b) CHECK_STRING_EQ(expectf, a, b)
+#define EXPECT_NULL(a) CHECK_NULL(expectf, a)
+#define EXPECT_NOT_NULL(a) CHECK_NOT_NULL(expectf, a)
+#define EXPECT_FALSE(a) CHECK_FALSE(expectf, a)
+#define EXPECT_FLOAT_EQ(a, b) CHECK_FLOAT_NEAR(expectf, a, b, 1e-6)
+#define EXPECT_FLOAT_NEAR(a, b, tolerance) CHECK_FLOAT_NEAR(expectf, a, b, tolerance)
+#define EXPECT_EQ_INT(a, b) CHECK_EQ_INT(expectf, a, b)
+#define EXPECT_EQ_UINT(a, b) CHECK_EQ_UINT(expectf, a, b)
+#define EXPECT_EQ_PTR(a, b) CHECK_EQ_PTR(expectf, a, b)
+#define EXPECT_MEM_EQ(a, b, size) CHECK_MEM_EQ(expectf, a, b, size)
+#define EXPECT_NE(a, b) CHECK_COMPARE(expectf, a, !=, b)
+#define EXPECT_LT(a, b) CHECK_COMPARE(expectf, a, <, b)
+#define EXPECT_LE(a, b) CHECK_COMPARE(expectf, a, <=, b)
+#define EXPECT_GT(a, b) CHECK_COMPARE(expectf, a, >, b)
+#define EXPECT_GE(a, b) CHECK_COMPARE(expectf, a, >=, b)
 
 typedef void test_fn();
 
 struct Test {
     const char *name;
     test_fn *test;
+    test_fn *setup;
+    test_fn *teardown;
 };
 
-void fail(char *msg);
+#if defined(__GNUC__)
+#define PRINTF_FORMAT __attribute__((format(printf, 1, 2)))
+#else
+#define PRINTF_FORMAT
+#endif
+
+void fail(const char *msg);
+PRINTF_FORMAT void failf(const char *format, ...);
+PRINTF_FORMAT void expectf(const char *format, ...);
+void reset_failures(void);
+void print_failures(const char *prefix);
+int run_step(test_fn *step);
+int run_test(struct Test *test);
+int run_suite_test(struct Test *suite, struct Test *test);
+void format_signed_value(char *buffer, long long value);
+void format_unsigned_value(char *buffer, unsigned long long value);
+void format_float_value(char *buffer, long double value);
+void format_pointer_value(char *buffer, const void *value);
 "#;
 
-pub fn get_test_files() -> Vec<PathBuf> {
-    let mut test_files = Vec::new();
-    for file in std::fs::read_dir("tests").unwrap() {
-        let file = file.unwrap();
-        let path = file.path();
-        match file.file_type().unwrap() {
-            t if t.is_dir() => (),
-            t if t.is_file() => {
-                if path.extension().unwrap() == "c"
-                    && file.file_name().into_string().unwrap() != "test_framework.c"
-                    && file.file_name().into_string().unwrap() != "tests.c"
+/// Finds the test files anywhere under `tests`, leaving out the generated ones.
+pub fn get_test_files() -> Result<Vec<PathBuf>, String> {
+    sources::find_sources(
+        &["tests/**/*.c".to_string()],
+        &[
+            "tests/test_framework.c".to_string(),
+            "tests/tests.c".to_string(),
+        ],
+    )
+}
+
+const DECLARATIONS: [&str; 6] = [
+    "TEST",
+    "TEST_TIMEOUT",
+    "SETUP",
+    "TEARDOWN",
+    "SUITE_SETUP",
+    "SUITE_TEARDOWN",
+];
+
+/// A use of one of the framework macros at the top level of a test file.
+#[derive(Debug, PartialEq)]
+struct Declaration {
+    line: usize,
+    name: String,
+    args: Vec<String>,
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Finds the declarations outside of function bodies, skipping string and character
+/// literals. The arguments may span several lines.
+fn get_declarations(lines: &[(usize, String)]) -> Vec<Declaration> {
+    let chars: Vec<(usize, char)> = lines
+        .iter()
+        .flat_map(|(line, text)| text.chars().chain(['\n']).map(move |c| (*line, c)))
+        .collect();
+    let mut declarations = Vec::new();
+    let mut depth = 0;
+    let mut index = 0;
+
+    while index < chars.len() {
+        let (line, c) = chars[index];
+        match c {
+            '"' | '\'' => {
+                index += 1;
+                while index < chars.len() && chars[index].1 != c && chars[index].1 != '\n' {
+                    if chars[index].1 == '\\' {
+                        index += 1;
+                    }
+                    index += 1;
+                }
+            }
+            '{' => depth += 1,
+            '}' => depth = std::cmp::max(depth, 1) - 1,
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = index;
+                while index < chars.len()
+                    && (chars[index].1.is_ascii_alphanumeric() || chars[index].1 == '_')
                 {
-                    test_files.push(path);
+                    index += 1;
+                }
+                let name: String = chars[start..index].iter().map(|(_, c)| c).collect();
+                if depth > 0 || !DECLARATIONS.contains(&name.as_str()) {
+                    continue;
                 }
+                let mut open = index;
+                while open < chars.len() && chars[open].1.is_whitespace() {
+                    open += 1;
+                }
+                if open == chars.len() || chars[open].1 != '(' {
+                    continue;
+                }
+
+                let mut args = vec![String::new()];
+                let mut parens = 0;
+                index = open + 1;
+                while index < chars.len() {
+                    match chars[index].1 {
+                        ')' if parens == 0 => break,
+                        ',' if parens == 0 => args.push(String::new()),
+                        c => {
+                            parens += (c == '(') as i32 - (c == ')') as i32;
+                            args.last_mut().unwrap().push(c);
+                        }
+                    }
+                    index += 1;
+                }
+                let mut args: Vec<String> = args.iter().map(|arg| arg.trim().to_string()).collect();
+                if args == [""] {
+                    args.clear();
+                }
+                declarations.push(Declaration { line, name, args });
             }
             _ => (),
         }
+        index += 1;
     }
-    test_files
+    declarations
 }
 
-fn get_tests_from_files(test_files: Vec<PathBuf>, test: &Test) -> Tests {
-    let mut tests = Vec::new();
-    for file in &test_files {
-        let file = std::fs::read_to_string(file).unwrap();
-        for line in file.lines() {
-            if line.starts_with("TEST(") {
-                if let Some(single) = &test.single {
-                    if line.contains(single) {
-                        tests.push(
-                            line.split("(").collect::<Vec<&str>>()[1]
-                                .split(")")
-                                .collect::<Vec<&str>>()[0]
-                                .to_string(),
-                        );
-                    }
-                } else {
-                    tests.push(
-                        line.split("(").collect::<Vec<&str>>()[1]
-                            .split(")")
-                            .collect::<Vec<&str>>()[0]
-                            .to_string(),
-                    );
+#[derive(Debug, Default, PartialEq)]
+struct FileTests {
+    tests: Vec<TestCase>,
+    fixtures: Fixtures,
+    suite: Fixtures,
+    /// Tests the compiler does not see with the current profile.
+    disabled: Vec<TestCase>,
+}
+
+/// The lines of a test file that the compiler sees with the flags of the profile.
+fn get_compiled_lines(path: &Path, config: &Config) -> Result<BTreeSet<usize>, String> {
+    let mut command = Command::new(get_cc(&config.toolchain));
+    command
+        .args(get_cflags(config, false))
+        .arg(TEST_INCLUDE_FLAG)
+        .arg("-E")
+        .arg(path);
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to preprocess test file `{}`: {}",
+            path.to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(preprocessor::get_output_lines(
+        &String::from_utf8_lossy(&output.stdout),
+        &path.to_string_lossy(),
+    ))
+}
+
+/// Finds the tests and fixtures of a test file. Conditionals are evaluated with `defines`,
+/// and when a declaration depends on other macros `get_compiled_lines` asks the compiler.
+fn get_tests_from_source(
+    path: &Path,
+    source: &str,
+    defines: &Defines,
+    get_compiled_lines: impl FnOnce() -> Result<BTreeSet<usize>, String>,
+) -> Result<FileTests, String> {
+    let file = path.to_string_lossy();
+    let lines = preprocessor::get_lines(source, &file, defines)?;
+    let select = |keep: &dyn Fn(Activity) -> bool| -> Vec<(usize, String)> {
+        lines
+            .iter()
+            .filter(|(_, _, activity)| keep(*activity))
+            .map(|(line, text, _)| (*line, text.clone()))
+            .collect()
+    };
+    let unknown: BTreeSet<usize> = lines
+        .iter()
+        .filter(|(_, _, activity)| *activity == Activity::Unknown)
+        .map(|(line, _, _)| *line)
+        .collect();
+
+    let mut declarations = get_declarations(&select(&|activity| activity != Activity::Inactive));
+    let mut disabled = get_declarations(&select(&|activity| activity == Activity::Inactive));
+    if declarations
+        .iter()
+        .any(|declaration| unknown.contains(&declaration.line))
+    {
+        let compiled = get_compiled_lines()?;
+        let (seen, unseen) = declarations.into_iter().partition(|declaration| {
+            !unknown.contains(&declaration.line) || compiled.contains(&declaration.line)
+        });
+        declarations = seen;
+        disabled.extend(unseen);
+    }
+
+    let mut found = FileTests::default();
+    for declaration in disabled {
+        let args = &declaration.args;
+        if declaration.name.starts_with("TEST") && !args.is_empty() && is_identifier(&args[0]) {
+            found.disabled.push(TestCase {
+                name: args[0].clone(),
+                file: path.to_path_buf(),
+                timeout: None,
+            });
+        }
+    }
+    for declaration in declarations {
+        let error = |message: &str| {
+            Err(format!(
+                "{}:{}: `{}` {}",
+                file, declaration.line, declaration.name, message
+            ))
+        };
+        let args = &declaration.args;
+        match declaration.name.as_str() {
+            "TEST" | "TEST_TIMEOUT" => {
+                let expected = if declaration.name == "TEST" { 1 } else { 2 };
+                if args.len() != expected || !is_identifier(&args[0]) {
+                    return error("expects a test name");
                 }
+                let timeout = match args.get(1).map(|timeout| timeout.parse()) {
+                    Some(Ok(timeout)) => Some(timeout),
+                    Some(Err(_)) => return error("expects a timeout in milliseconds"),
+                    None => None,
+                };
+                found.tests.push(TestCase {
+                    name: args[0].clone(),
+                    file: path.to_path_buf(),
+                    timeout,
+                });
             }
+            _ if !args.is_empty() => return error("takes no arguments"),
+            "SETUP" => found.fixtures.setup = true,
+            "TEARDOWN" => found.fixtures.teardown = true,
+            "SUITE_SETUP" => found.suite.setup = true,
+            _ => found.suite.teardown = true,
         }
     }
-    Tests { test_files, tests }
+    Ok(found)
 }
 
-pub fn get_tests(test: &Test) -> Tests {
-    let test_files = get_test_files();
-    get_tests_from_files(test_files, test)
+/// Whether the test is selected with `--single` or `--filter`.
+fn is_selected(case: &TestCase, test: &Test) -> bool {
+    match (&test.single, &test.filter) {
+        (Some(single), _) => case.name == *single,
+        (None, Some(filter)) => sources::matches_name(filter, &case.name),
+        (None, None) => true,
+    }
 }
 
-pub fn write_tests_to_file(test: &Test) {
-    let tests = get_tests(test);
+/// Keeps the tests selected with `--single` or `--filter`, failing when none are left.
+fn filter_tests(tests: Vec<TestCase>, test: &Test) -> Result<Vec<TestCase>, String> {
+    let error = match (&test.single, &test.filter) {
+        (Some(single), _) => format!("No test named `{}`", single),
+        (None, Some(filter)) => format!("No tests match `{}`", filter),
+        (None, None) => return Ok(tests),
+    };
+    let tests: Vec<TestCase> = tests
+        .into_iter()
+        .filter(|case| is_selected(case, test))
+        .collect();
+    if tests.is_empty() {
+        return Err(error);
+    }
+    Ok(tests)
+}
+
+fn get_tests_from_files(
+    test_files: Vec<PathBuf>,
+    test: &Test,
+    config: &Config,
+) -> Result<Tests, String> {
+    let defines = get_scan_options(config).defines;
+    let mut tests = Vec::new();
+    let mut fixtures = BTreeMap::new();
+    let mut suites = BTreeMap::new();
+    for path in &test_files {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read test file `{}`: {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+        let found =
+            get_tests_from_source(path, &source, &defines, || get_compiled_lines(path, config))?;
+        for case in &found.disabled {
+            let shadowed = found.tests.iter().any(|other| other.name == case.name);
+            if !shadowed && is_selected(case, test) {
+                eprintln!(
+                    "Note: Test `{}` in `{}` is disabled by the preprocessor",
+                    case.name,
+                    path.to_string_lossy()
+                );
+            }
+        }
+        tests.extend(found.tests);
+        if found.fixtures != Fixtures::default() {
+            fixtures.insert(path.clone(), found.fixtures);
+        }
+        if found.suite != Fixtures::default() {
+            suites.insert(path.clone(), found.suite);
+        }
+    }
+
+    Ok(Tests {
+        test_files,
+        tests: filter_tests(tests, test)?,
+        fixtures,
+        suites,
+    })
+}
+
+pub fn get_tests(test: &Test, config: &Config) -> Result<Tests, String> {
+    let test_files = get_test_files()?;
+    get_tests_from_files(test_files, test, config)
+}
+
+/// Generates the runner at `path`, which includes the test files and runs the tests.
+pub fn write_tests_to_file(path: &Path, tests: &Tests) -> Result<(), String> {
     let test_count = tests.tests.len();
-    let mut file = std::fs::File::create("tests/tests.c").unwrap();
+    let mut file = match std::fs::File::create(path) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(format!(
+                "Failed to create test runner `{}`: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        }
+    };
     file.write_all("#include \"test_framework.h\"\n\n".as_bytes())
         .unwrap();
-
-    for test_file in tests.test_files {
-        file.write_all(
-            format!("#include \"{}\"\n", test_file.to_str().unwrap())
-                .replace("tests/", "")
-                .as_bytes(),
+    // Older frameworks lack the fixtures the runner calls, so fail with a clear message.
+    file.write_all(
+        format!(
+            "#if !defined(TEST_FRAMEWORK_VERSION) || TEST_FRAMEWORK_VERSION != {}\n\
+             #error \"test_framework.h does not match this c-builder version, delete it to regenerate it\"\n\
+             #endif\n\n",
+            FRAMEWORK_VERSION
         )
-        .unwrap();
+        .as_bytes(),
+    )
+    .unwrap();
+
+    for (id, test_file) in tests.test_files.iter().enumerate() {
+        file.write_all(format!("#undef TEST_FILE_ID\n#define TEST_FILE_ID {}\n", id).as_bytes())
+            .unwrap();
+        let include = match test_file.strip_prefix(path.parent().unwrap_or(Path::new(""))) {
+            Ok(include) => include.to_path_buf(),
+            Err(_) => match test_file.canonicalize() {
+                Ok(include) => include,
+                Err(e) => return Err(format!("Failed to find test file: {}", e)),
+            },
+        };
+        file.write_all(format!("#include \"{}\"\n", include.to_string_lossy()).as_bytes())
+            .unwrap();
     }
 
     file.write_all("\n".as_bytes()).unwrap();
     file.write_all("#include <stdio.h>\n".as_bytes()).unwrap();
+    file.write_all("#include <string.h>\n".as_bytes()).unwrap();
     file.write_all("#include <setjmp.h>\n".as_bytes()).unwrap();
 
     file.write_all("\n".as_bytes()).unwrap();
     file.write_all("extern jmp_buf jmpbuf;\n".as_bytes())
         .unwrap();
-    file.write_all("extern char *error_msg;\n\n".as_bytes())
+    file.write_all("extern char error_msg[];\n\n".as_bytes())
         .unwrap();
 
     file.write_all("struct Test tests[] = {\n".as_bytes())
         .unwrap();
-    for test in tests.tests {
-        file.write_all(format!("{{ \"{}\", test_{} }},\n", test, test).as_bytes())
-            .unwrap();
+    for test in &tests.tests {
+        let id = tests
+            .test_files
+            .iter()
+            .position(|path| *path == test.file)
+            .unwrap_or_default();
+        let fixtures = tests.fixtures.get(&test.file).cloned().unwrap_or_default();
+        file.write_all(
+            format!(
+                "{{ \"{}\", test_{}, {}, {} }},\n",
+                test.name,
+                test.name,
+                get_fixture("setup_", id, fixtures.setup),
+                get_fixture("teardown_", id, fixtures.teardown)
+            )
+            .as_bytes(),
+        )
+        .unwrap();
     }
 
     file.write_all("};\n\n".as_bytes()).unwrap();
+    let suite = tests.get_suite();
+    file.write_all(
+        format!(
+            "struct Test suite = {{ \"suite\", NULL, {}, {} }};\n\n",
+            get_fixture("suite_setup", "", suite.setup),
+            get_fixture("suite_teardown", "", suite.teardown)
+        )
+        .as_bytes(),
+    )
+    .unwrap();
 
-    file.write_all("int main() {\n".as_bytes()).unwrap();
+    file.write_all("int main(int argc, char **argv) {\n".as_bytes())
+        .unwrap();
+    file.write_all("if (argc > 1) {\n".as_bytes()).unwrap();
+    file.write_all(format!("for (int i = 0; i < {}; i++) {{\n", test_count).as_bytes())
+        .unwrap();
+    file.write_all("if (strcmp(argv[1], tests[i].name) != 0) {\ncontinue;\n}\n".as_bytes())
+        .unwrap();
+    file.write_all("if (run_suite_test(&suite, &tests[i]) == 0) {\nreturn 0;\n}\n".as_bytes())
+        .unwrap();
+    file.write_all(
+        format!(
+            "printf(\"\\n\");\nprint_failures(\"{}\");\n",
+            FAILURE_MARKER
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    file.write_all("return 1;\n}\n".as_bytes()).unwrap();
+    file.write_all("fprintf(stderr, \"No test named `%s`\\n\", argv[1]);\n".as_bytes())
+        .unwrap();
+    file.write_all("return 2;\n}\n\n".as_bytes()).unwrap();
+    file.write_all("int failed = 0;\n".as_bytes()).unwrap();
+    file.write_all("int suite_failed = 0;\n".as_bytes())
+        .unwrap();
+    file.write_all("if (run_step(suite.setup)) {\n".as_bytes())
+        .unwrap();
+    file.write_all("printf(\"Suite setup failed: %s\\n\", error_msg);\n".as_bytes())
+        .unwrap();
+    file.write_all(format!("failed = {};\n", test_count).as_bytes())
+        .unwrap();
+    file.write_all("} else {\n".as_bytes()).unwrap();
     file.write_all(format!("for (int i = 0; i < {}; i++) {{\n", test_count).as_bytes())
         .unwrap();
-    file.write_all("if (setjmp(jmpbuf) == 0) {\n".as_bytes())
+    file.write_all("if (run_test(&tests[i]) == 0) {\n".as_bytes())
         .unwrap();
-    file.write_all("tests[i].test();\n".as_bytes()).unwrap();
     file.write_all("printf(\"Test `%s` passed\\n\", tests[i].name);\n".as_bytes())
         .unwrap();
     file.write_all("} else {\n".as_bytes()).unwrap();
     file.write_all("printf(\"Test `%s` failed: %s\\n\", tests[i].name, error_msg);\n".as_bytes())
         .unwrap();
-    file.write_all("}\n".as_bytes()).unwrap();
-    file.write_all("}\nreturn 0;\n}\n".as_bytes()).unwrap();
+    file.write_all("failed++;\n".as_bytes()).unwrap();
+    file.write_all("}\n}\n}\n".as_bytes()).unwrap();
+    file.write_all("reset_failures();\n".as_bytes()).unwrap();
+    file.write_all("if (run_step(suite.teardown)) {\n".as_bytes())
+        .unwrap();
+    file.write_all("printf(\"Suite teardown failed: %s\\n\", error_msg);\n".as_bytes())
+        .unwrap();
+    file.write_all("suite_failed = 1;\n}\n".as_bytes()).unwrap();
+    file.write_all(
+        format!(
+            "printf(\"\\n%d passed; %d failed\\n\", {} - failed, failed);\n",
+            test_count
+        )
+        .as_bytes(),
+    )
+    .unwrap();
+    file.write_all("return failed != 0 || suite_failed;\n}\n".as_bytes())
+        .unwrap();
+    Ok(())
+}
+
+fn get_fixture(name: &str, id: impl std::fmt::Display, defined: bool) -> String {
+    if defined {
+        format!("{}{}", name, id)
+    } else {
+        "NULL".to_string()
+    }
+}
+
+/// Changed whenever the generated runner needs a newer framework, so existing framework
+/// files are replaced.
+const FRAMEWORK_VERSION: u32 = 1;
+
+/// The first line of the framework files, telling which version wrote them.
+fn get_framework_marker() -> String {
+    format!(
+        "/* c-builder test framework version {} */",
+        FRAMEWORK_VERSION
+    )
+}
+
+/// Writes a framework file when it is missing or was written by another version of
+/// `c-builder`, returning whether an existing file was replaced. Files of the current
+/// version are left alone, so changes made to them are kept.
+fn write_framework_file(path: &Path, contents: &str) -> std::io::Result<bool> {
+    let marker = get_framework_marker();
+    let exists = match std::fs::read_to_string(path) {
+        Ok(existing) if existing.lines().next() == Some(marker.as_str()) => return Ok(false),
+        Ok(_) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e),
+    };
+    std::fs::write(path, format!("{}\n{}", marker, contents))?;
+    Ok(exists)
 }
 
 pub fn create_test_framework(path: &str) -> Result<(), String> {
@@ -184,13 +870,230 @@ pub fn create_test_framework(path: &str) -> Result<(), String> {
     let test_framework_c = dir.join("test_framework.c");
     let test_framework_h = dir.join("test_framework.h");
 
-    match std::fs::write(test_framework_c, TEST_FRAMEWORK_C) {
-        Ok(_) => (),
+    let replaced_c = match write_framework_file(&test_framework_c, TEST_FRAMEWORK_C) {
+        Ok(replaced) => replaced,
         Err(e) => return Err(format!("Failed to create test framework source: {}", e)),
-    }
-    match std::fs::write(test_framework_h, TEST_FRAMEWORK_H) {
-        Ok(_) => (),
+    };
+    let replaced_h = match write_framework_file(&test_framework_h, TEST_FRAMEWORK_H) {
+        Ok(replaced) => replaced,
         Err(e) => return Err(format!("Failed to create test framework header: {}", e)),
     };
+    for (replaced, file) in [
+        (replaced_c, test_framework_c),
+        (replaced_h, test_framework_h),
+    ] {
+        if replaced {
+            eprintln!(
+                "Note: Replaced `{}` with the test framework of this `c-builder` version",
+                file.to_string_lossy()
+            );
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test(single: Option<&str>, filter: Option<&str>) -> Test {
+        Test {
+            release: false,
+            profile: None,
+            single: single.map(str::to_string),
+            filter: filter.map(str::to_string),
+            jobs: 1,
+            format: None,
+            output: None,
+        }
+    }
+
+    fn get_case(name: &str, timeout: Option<u64>) -> TestCase {
+        TestCase {
+            name: name.to_string(),
+            file: "tests/a.c".into(),
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_get_tests_from_source() {
+        let source = r#"#include "test_framework.h"
+
+  TEST(indented) { ASSERT(1); }
+// TEST(commented) {}
+/* TEST(block_commented) {} */
+#if 0
+TEST(disabled) {}
+#endif
+TEST_TIMEOUT(
+    slow,
+    500
+) {
+    const char *s = "TEST(in_string) {";
+    TEST(in_body);
+}
+SETUP() {}
+SUITE_TEARDOWN() {}
+"#;
+        let found = get_tests_from_source(Path::new("tests/a.c"), source, &Defines::new(), || {
+            panic!("the compiler is not needed")
+        })
+        .unwrap();
+        assert_eq!(
+            found.tests,
+            [get_case("indented", None), get_case("slow", Some(500))]
+        );
+        assert_eq!(found.disabled, [get_case("disabled", None)]);
+        assert_eq!(
+            found.fixtures,
+            Fixtures {
+                setup: true,
+                teardown: false
+            }
+        );
+        assert_eq!(
+            found.suite,
+            Fixtures {
+                setup: false,
+                teardown: true
+            }
+        );
+
+        assert_eq!(
+            get_tests_from_source(
+                Path::new("tests/a.c"),
+                "\nTEST(1x) {}\n",
+                &Defines::new(),
+                || Ok(BTreeSet::new())
+            )
+            .unwrap_err(),
+            "tests/a.c:2: `TEST` expects a test name"
+        );
+        assert_eq!(
+            get_tests_from_source(
+                Path::new("tests/a.c"),
+                "TEST_TIMEOUT(a, soon) {}\n",
+                &Defines::new(),
+                || Ok(BTreeSet::new())
+            )
+            .unwrap_err(),
+            "tests/a.c:1: `TEST_TIMEOUT` expects a timeout in milliseconds"
+        );
+    }
+
+    #[test]
+    fn test_get_tests_from_source_unknown() {
+        let source = r#"#include <limits.h>
+#if __STDC_VERSION__ >= 201112L
+TEST(c11_only) {}
+#endif
+#ifdef CHAR_BIT
+TEST(from_header) {}
+#endif
+#ifdef _WIN32
+TEST(windows_only) {}
+#endif
+TEST(always) {}
+"#;
+        let found = get_tests_from_source(Path::new("tests/a.c"), source, &Defines::new(), || {
+            Ok(BTreeSet::from([1, 3, 6, 11]))
+        })
+        .unwrap();
+        assert_eq!(
+            found.tests,
+            [
+                get_case("c11_only", None),
+                get_case("from_header", None),
+                get_case("always", None)
+            ]
+        );
+        assert_eq!(found.disabled, [get_case("windows_only", None)]);
+    }
+
+    #[test]
+    fn test_framework_version() {
+        let define = format!("#define TEST_FRAMEWORK_VERSION {}\n", FRAMEWORK_VERSION);
+        assert!(TEST_FRAMEWORK_H.contains(&define));
+    }
+
+    #[test]
+    fn test_write_framework_file() {
+        let path = std::env::temp_dir().join("c-builder-test-framework.h");
+        let _ = std::fs::remove_file(&path);
+        assert!(!write_framework_file(&path, "int a;\n").unwrap());
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents, format!("{}\nint a;\n", get_framework_marker()));
+
+        // Changes to a file of the current version are kept.
+        std::fs::write(&path, format!("{}\nint b;\n", get_framework_marker())).unwrap();
+        assert!(!write_framework_file(&path, "int a;\n").unwrap());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("int b;\n"));
+
+        std::fs::write(&path, "int old;\n").unwrap();
+        assert!(write_framework_file(&path, "int a;\n").unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_get_file_tests() {
+        let fixtures = Fixtures {
+            setup: true,
+            teardown: false,
+        };
+        let tests = Tests {
+            test_files: vec!["tests/a.c".into(), "tests/net/b.c".into()],
+            tests: vec![
+                get_case("a", None),
+                TestCase {
+                    file: "tests/net/b.c".into(),
+                    ..get_case("b", None)
+                },
+            ],
+            fixtures: BTreeMap::from([("tests/net/b.c".into(), fixtures.clone())]),
+            suites: BTreeMap::from([("tests/a.c".into(), fixtures.clone())]),
+        };
+        assert_eq!(tests.get_suite(), fixtures);
+
+        let file_tests = tests.get_file_tests(Path::new("tests/net/b.c"));
+        assert_eq!(file_tests.test_files, [PathBuf::from("tests/net/b.c")]);
+        assert_eq!(file_tests.tests.len(), 1);
+        assert_eq!(file_tests.tests[0].name, "b");
+        assert_eq!(file_tests.fixtures.len(), 1);
+        assert_eq!(file_tests.get_suite(), Fixtures::default());
+    }
+
+    #[test]
+    fn test_filter_tests() {
+        let tests = vec![
+            get_case("parse", None),
+            get_case("parse_number", None),
+            get_case("eval", None),
+        ];
+        assert_eq!(
+            filter_tests(tests.clone(), &get_test(Some("parse"), None)),
+            Ok(vec![get_case("parse", None)])
+        );
+        assert_eq!(
+            filter_tests(tests.clone(), &get_test(None, Some("parse*"))),
+            Ok(vec![
+                get_case("parse", None),
+                get_case("parse_number", None)
+            ])
+        );
+        assert_eq!(
+            filter_tests(tests.clone(), &get_test(Some("pars"), None)),
+            Err("No test named `pars`".to_string())
+        );
+        assert_eq!(
+            filter_tests(tests.clone(), &get_test(None, Some("x*"))),
+            Err("No tests match `x*`".to_string())
+        );
+        assert_eq!(
+            filter_tests(tests.clone(), &get_test(None, None)),
+            Ok(tests)
+        );
+    }
+}
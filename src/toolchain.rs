@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    Gcc,
+    Clang,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Toolchain {
+    pub cc: Option<String>,
+    pub ar: Option<String>,
+    pub ld: Option<String>,
+    pub dialect: Option<Dialect>,
+}
+
+const DEFAULT_CC: &str = "gcc";
+const DEFAULT_AR: &str = "ar";
+
+/// Overrides the configured programs with the `CC`, `AR` and `LD` environment variables.
+pub fn apply_env(toolchain: &mut Toolchain) {
+    for (variable, value) in [
+        ("CC", &mut toolchain.cc),
+        ("AR", &mut toolchain.ar),
+        ("LD", &mut toolchain.ld),
+    ] {
+        if let Ok(env) = std::env::var(variable) {
+            if !env.is_empty() {
+                *value = Some(env);
+            }
+        }
+    }
+}
+
+pub fn get_cc(toolchain: &Toolchain) -> &str {
+    toolchain.cc.as_deref().unwrap_or(DEFAULT_CC)
+}
+
+pub fn get_ar(toolchain: &Toolchain) -> &str {
+    toolchain.ar.as_deref().unwrap_or(DEFAULT_AR)
+}
+
+/// The linker is invoked as a compiler driver, so it defaults to the compiler.
+pub fn get_ld(toolchain: &Toolchain) -> &str {
+    toolchain.ld.as_deref().unwrap_or(get_cc(toolchain))
+}
+
+pub fn get_dialect(toolchain: &Toolchain) -> Dialect {
+    if let Some(dialect) = toolchain.dialect {
+        return dialect;
+    }
+
+    let name = Path::new(get_cc(toolchain))
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if name.contains("clang") {
+        Dialect::Clang
+    } else {
+        Dialect::Gcc
+    }
+}
+
+pub fn get_color_flag(dialect: Dialect) -> &'static str {
+    match dialect {
+        Dialect::Gcc => "-fdiagnostics-color=always",
+        Dialect::Clang => "-fcolor-diagnostics",
+    }
+}
+
+pub fn get_std_flag(dialect: Dialect, std: &str) -> String {
+    // Older clang releases only know the draft name of C23.
+    let std = match (dialect, std) {
+        (Dialect::Clang, "c23") => "c2x",
+        (Dialect::Clang, "gnu23") => "gnu2x",
+        (_, std) => std,
+    };
+    format!("-std={}", std)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_dialect() {
+        let mut toolchain = Toolchain::default();
+        assert_eq!(get_dialect(&toolchain), Dialect::Gcc);
+
+        toolchain.cc = Some("/usr/bin/clang-18".to_string());
+        assert_eq!(get_dialect(&toolchain), Dialect::Clang);
+
+        toolchain.cc = Some("arm-none-eabi-gcc".to_string());
+        assert_eq!(get_dialect(&toolchain), Dialect::Gcc);
+
+        toolchain.dialect = Some(Dialect::Clang);
+        assert_eq!(get_dialect(&toolchain), Dialect::Clang);
+    }
+
+    #[test]
+    fn test_get_ld() {
+        let mut toolchain = Toolchain {
+            cc: Some("clang".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(get_ld(&toolchain), "clang");
+
+        toolchain.ld = Some("gcc".to_string());
+        assert_eq!(get_ld(&toolchain), "gcc");
+    }
+
+    #[test]
+    fn test_get_std_flag() {
+        assert_eq!(get_std_flag(Dialect::Gcc, "c23"), "-std=c23");
+        assert_eq!(get_std_flag(Dialect::Clang, "c23"), "-std=c2x");
+        assert_eq!(get_std_flag(Dialect::Clang, "c11"), "-std=c11");
+    }
+}
@@ -0,0 +1,388 @@
+use crate::build::{get_binaries, get_build_options, get_includes, get_scan_options};
+use crate::cli::{Build, Tree, TreeFormat};
+use crate::includes::{get_dependencies, Dependency, IncludeType, ScanOptions};
+use crate::library::{get_library_entry, get_library_includes};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Graph {
+    roots: Vec<String>,
+    edges: BTreeMap<String, Vec<String>>,
+    cycles: Vec<Vec<String>>,
+}
+
+fn get_node_name(path: &Path, directory: &Path) -> String {
+    path.strip_prefix(directory)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Scans every translation unit and the headers they reach, keeping each include as an edge.
+/// System headers are leaves named like `<stdio.h>`.
+fn build_graph(
+    units: &[PathBuf],
+    options: &ScanOptions,
+    directory: &Path,
+) -> Result<Graph, String> {
+    let mut graph = Graph::default();
+    let mut queue: VecDeque<PathBuf> = units.iter().cloned().collect();
+    for unit in units {
+        let name = get_node_name(unit, directory);
+        if !graph.roots.contains(&name) {
+            graph.roots.push(name);
+        }
+    }
+
+    while let Some(file) = queue.pop_front() {
+        let name = get_node_name(&file, directory);
+        if graph.edges.contains_key(&name) {
+            continue;
+        }
+
+        let mut children = Vec::new();
+        for dependency in get_dependencies(&file, options)? {
+            let child = match dependency {
+                Dependency::System(name) => format!("<{}>", name),
+                Dependency::Header { path, .. } => {
+                    let child = get_node_name(&path, directory);
+                    queue.push_back(path);
+                    child
+                }
+            };
+            if !children.contains(&child) {
+                children.push(child);
+            }
+        }
+        graph.edges.insert(name, children);
+    }
+
+    graph.cycles = find_cycles(&graph);
+    Ok(graph)
+}
+
+fn visit(
+    node: &str,
+    graph: &Graph,
+    stack: &mut Vec<String>,
+    visited: &mut Vec<String>,
+    cycles: &mut Vec<Vec<String>>,
+) {
+    if let Some(position) = stack.iter().position(|other| other == node) {
+        let mut cycle = stack[position..].to_vec();
+        cycle.push(node.to_string());
+        cycles.push(cycle);
+        return;
+    }
+    if visited.iter().any(|other| other == node) {
+        return;
+    }
+    visited.push(node.to_string());
+
+    stack.push(node.to_string());
+    for child in graph.edges.get(node).into_iter().flatten() {
+        visit(child, graph, stack, visited, cycles);
+    }
+    stack.pop();
+}
+
+fn find_cycles(graph: &Graph) -> Vec<Vec<String>> {
+    let mut cycles = Vec::new();
+    let mut visited = Vec::new();
+    for root in &graph.roots {
+        visit(root, graph, &mut Vec::new(), &mut visited, &mut cycles);
+    }
+    cycles
+}
+
+fn matches_header(node: &str, header: &str) -> bool {
+    node == header || node.ends_with(&format!("/{}", header)) || node == format!("<{}>", header)
+}
+
+/// Reverses the edges, so the tree shows every file that includes the header, up to the
+/// translation units.
+fn invert(graph: &Graph, header: &str) -> Result<Graph, String> {
+    let mut reversed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (node, children) in &graph.edges {
+        for child in children {
+            reversed
+                .entry(child.clone())
+                .or_default()
+                .push(node.clone());
+        }
+    }
+
+    let mut inverted = Graph::default();
+    for node in reversed.keys().chain(graph.edges.keys()) {
+        if matches_header(node, header) && !inverted.roots.contains(node) {
+            inverted.roots.push(node.clone());
+        }
+    }
+    if inverted.roots.is_empty() {
+        return Err(format!(
+            "Header `{}` is not included by any translation unit",
+            header
+        ));
+    }
+
+    let mut queue: VecDeque<String> = inverted.roots.iter().cloned().collect();
+    while let Some(node) = queue.pop_front() {
+        if inverted.edges.contains_key(&node) {
+            continue;
+        }
+        let parents = reversed.remove(&node).unwrap_or_default();
+        queue.extend(parents.iter().cloned());
+        inverted.edges.insert(node, parents);
+    }
+
+    inverted.cycles = find_cycles(&inverted);
+    Ok(inverted)
+}
+
+fn render_node(
+    node: &str,
+    graph: &Graph,
+    prefix: &str,
+    stack: &mut Vec<String>,
+    expanded: &mut Vec<String>,
+    output: &mut String,
+) {
+    let children = graph.edges.get(node).cloned().unwrap_or_default();
+    stack.push(node.to_string());
+    for (index, child) in children.iter().enumerate() {
+        let last = index == children.len() - 1;
+        let has_children = graph.edges.get(child).is_some_and(|c| !c.is_empty());
+        let marker = if stack.contains(child) {
+            " (cycle)"
+        } else if has_children && expanded.contains(child) {
+            " (*)"
+        } else {
+            ""
+        };
+        writeln!(
+            output,
+            "{}{}{}{}",
+            prefix,
+            if last { "└── " } else { "├── " },
+            child,
+            marker
+        )
+        .unwrap();
+
+        if marker.is_empty() {
+            expanded.push(child.clone());
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            render_node(child, graph, &prefix, stack, expanded, output);
+        }
+    }
+    stack.pop();
+}
+
+fn render_text(graph: &Graph) -> String {
+    let mut output = String::new();
+    let mut expanded = Vec::new();
+    for root in &graph.roots {
+        writeln!(output, "{}", root).unwrap();
+        expanded.push(root.clone());
+        render_node(root, graph, "", &mut Vec::new(), &mut expanded, &mut output);
+    }
+
+    if !graph.cycles.is_empty() {
+        writeln!(output, "\nInclude cycles:").unwrap();
+        for cycle in &graph.cycles {
+            writeln!(output, "  {}", cycle.join(" -> ")).unwrap();
+        }
+    }
+    output.trim_end().to_string()
+}
+
+fn is_cycle_edge(graph: &Graph, from: &str, to: &str) -> bool {
+    graph.cycles.iter().any(|cycle| {
+        cycle
+            .windows(2)
+            .any(|edge| edge[0] == from && edge[1] == to)
+    })
+}
+
+fn render_dot(graph: &Graph) -> String {
+    let mut output = String::from("digraph includes {\n");
+    for root in &graph.roots {
+        writeln!(output, "    {:?} [shape=box];", root).unwrap();
+    }
+    for (node, children) in &graph.edges {
+        for child in children {
+            let style = if is_cycle_edge(graph, node, child) {
+                " [color=red]"
+            } else {
+                ""
+            };
+            writeln!(output, "    {:?} -> {:?}{};", node, child, style).unwrap();
+        }
+    }
+    output.push('}');
+    output
+}
+
+fn render(graph: &Graph, format: &TreeFormat) -> Result<String, String> {
+    match format {
+        TreeFormat::Text => Ok(render_text(graph)),
+        TreeFormat::Dot => Ok(render_dot(graph)),
+        TreeFormat::Json => match serde_json::to_string_pretty(graph) {
+            Ok(json) => Ok(json),
+            Err(e) => Err(format!("Failed to serialize include graph: {}", e)),
+        },
+    }
+}
+
+pub fn tree(args: &Tree) -> Result<String, String> {
+    let config = get_build_options(&Build {
+        release: args.release,
+        benchmark: false,
+        profile: args.profile.clone(),
+        asm: false,
+        bin: None,
+    })?;
+    let directory = match std::env::current_dir().and_then(|dir| dir.canonicalize()) {
+        Ok(directory) => directory,
+        Err(e) => return Err(format!("Failed to get current directory: {}", e)),
+    };
+    let options = get_scan_options(&config);
+
+    let mut entries = Vec::new();
+    let mut includes = Vec::new();
+    for binary in get_binaries(&config) {
+        let entry = PathBuf::from(&binary.path);
+        includes.extend(get_includes(&config, &entry, &options)?);
+        entries.push(entry);
+    }
+    if let Some(library) = &config.lib {
+        let entry = get_library_entry(&config, library);
+        includes.extend(get_library_includes(&config, &entry)?);
+        entries.push(entry);
+    }
+
+    let mut sources = Vec::new();
+    for include in includes {
+        if let IncludeType::Local(path) = include.kind {
+            sources.push(path);
+        }
+    }
+    sources.sort();
+
+    let mut units = Vec::new();
+    for unit in entries.into_iter().chain(sources) {
+        let unit = match unit.canonicalize() {
+            Ok(unit) => unit,
+            Err(e) => {
+                return Err(format!(
+                    "Failed to find source `{}`: {}",
+                    unit.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+        if !units.contains(&unit) {
+            units.push(unit);
+        }
+    }
+
+    let graph = build_graph(&units, &options, &directory)?;
+    match &args.invert {
+        Some(header) => render(&invert(&graph, header)?, &args.format),
+        None => render(&graph, &args.format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_graph(roots: &[&str], edges: &[(&str, &[&str])]) -> Graph {
+        let mut graph = Graph {
+            roots: roots.iter().map(|root| root.to_string()).collect(),
+            edges: edges
+                .iter()
+                .map(|(node, children)| {
+                    (
+                        node.to_string(),
+                        children.iter().map(|child| child.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            cycles: Vec::new(),
+        };
+        graph.cycles = find_cycles(&graph);
+        graph
+    }
+
+    #[test]
+    fn test_render_text() {
+        let graph = get_graph(
+            &["src/main.c", "src/a.c"],
+            &[
+                ("src/main.c", &["src/a.h", "<stdio.h>"]),
+                ("src/a.c", &["src/a.h"]),
+                ("src/a.h", &["src/b.h"]),
+                ("src/b.h", &["src/a.h"]),
+            ],
+        );
+        assert_eq!(graph.cycles, [vec!["src/a.h", "src/b.h", "src/a.h"]]);
+        assert_eq!(
+            render_text(&graph),
+            "src/main.c
+├── src/a.h
+│   └── src/b.h
+│       └── src/a.h (cycle)
+└── <stdio.h>
+src/a.c
+└── src/a.h (*)
+
+Include cycles:
+  src/a.h -> src/b.h -> src/a.h"
+        );
+    }
+
+    #[test]
+    fn test_invert() {
+        let graph = get_graph(
+            &["src/main.c", "src/a.c", "src/b.c"],
+            &[
+                ("src/main.c", &["src/a.h"]),
+                ("src/a.c", &["src/a.h", "src/util.h"]),
+                ("src/b.c", &[]),
+                ("src/a.h", &["src/util.h"]),
+                ("src/util.h", &[]),
+            ],
+        );
+        let inverted = invert(&graph, "util.h").unwrap();
+        assert_eq!(inverted.roots, ["src/util.h"]);
+        assert_eq!(
+            render_text(&inverted),
+            "src/util.h
+├── src/a.c
+└── src/a.h
+    ├── src/a.c
+    └── src/main.c"
+        );
+
+        assert!(invert(&graph, "missing.h").is_err());
+    }
+
+    #[test]
+    fn test_render_dot() {
+        let graph = get_graph(&["main.c"], &[("main.c", &["a.h"]), ("a.h", &["a.h"])]);
+        assert_eq!(
+            render_dot(&graph),
+            "digraph includes {
+    \"main.c\" [shape=box];
+    \"a.h\" -> \"a.h\" [color=red];
+    \"main.c\" -> \"a.h\";
+}"
+        );
+    }
+}